@@ -5,7 +5,7 @@ mod cli;
 
 fn main() -> std::io::Result<()> {
     let out_dir = std::path::PathBuf::from(
-        std::env::var_os("OUT_DIR").ok_or_else(|| std::io::ErrorKind::NotFound)?,
+        std::env::var_os("OUT_DIR").ok_or(std::io::ErrorKind::NotFound)?,
     );
     let cmd = cli::Cli::command();
 
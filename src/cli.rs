@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
@@ -10,10 +10,120 @@ Networks are defined in JSON format with layers, neurons, and weights.
 ", long_about = None)]
 #[command(arg_required_else_help = true)]
 pub struct Cli {
+    #[arg(
+        short,
+        long,
+        global = true,
+        action = clap::ArgAction::Count,
+        help = "Increase logging verbosity (-v debug, -vv trace), RUST_LOG takes precedence"
+    )]
+    pub verbose: u8,
+    #[arg(
+        long,
+        global = true,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Number of threads used for parallel work, 1 disables parallelism (default: all cores)"
+    )]
+    pub threads: Option<u32>,
+    #[arg(
+        long,
+        global = true,
+        help = "Flush pending output to stdout before every log message so both streams stay in program order"
+    )]
+    pub deterministic_output: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Print errors with the context they happened in, e.g. the synapse being connected"
+    )]
+    pub verbose_errors: bool,
     #[command(subcommand)]
     pub command: Commands,
 }
 
+#[derive(Debug, Args)]
+pub struct LoadArgs {
+    #[arg(long, help = "Fail to load networks containing neurons deeper than this")]
+    pub max_depth: Option<u32>,
+    #[arg(long, help = "Fail to load networks containing neurons with more incoming synapses than this")]
+    pub max_fan_in: Option<usize>,
+    #[arg(
+        long,
+        help = "Fail to load networks whose activation names are not spelled exactly as saved, e.g. 'relu' instead of 'ReLU'"
+    )]
+    pub strict_activation_names: bool,
+    #[arg(
+        long,
+        help = "Recalculate the neuron depths saved in the configuration and fail to load it if they differ"
+    )]
+    pub verify_depths: bool,
+    #[arg(
+        long,
+        help = "Fail to load networks with suspicious parts like outputs without synapses, and skip input lines outside of the input bounds, instead of warning"
+    )]
+    pub strict: bool,
+    #[arg(
+        long,
+        value_name = "MAX",
+        help = "Bound every pre-activation into [-MAX, MAX] before its activation is applied, keeping activations like Gaussian from overflowing"
+    )]
+    pub clamp_preact: Option<f64>,
+}
+
+#[derive(Debug, Args)]
+pub struct SaveArgs {
+    #[arg(long, help = "Save the configuration on a single line without indentation")]
+    pub compact: bool,
+    #[arg(long, help = "Compress the saved configuration with gzip, loading detects it on its own")]
+    pub gzip: bool,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Loss {
+    /// (output - expected)^2
+    Squared,
+    /// (output - expected)^2 / 2
+    HalfSquared,
+    /// |output - expected|
+    Absolute,
+    /// -(expected * ln(output) + (1 - expected) * ln(1 - output)), for outputs in (0, 1)
+    CrossEntropy,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum FloatFormat {
+    /// Six digits after the decimal point, e.g. 0.000123
+    Fixed,
+    /// Mantissa and exponent, e.g. 1.23e-4
+    Scientific,
+    /// The fewest digits that read back as the same value
+    Shortest,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum GradReduction {
+    /// Average over the samples of the batch, keeping the step size independent of the batch size
+    Mean,
+    /// Sum over the samples of the batch, like mean with the learning rate multiplied by the batch size
+    Sum,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum OptimizerMethod {
+    /// Step against the gradient scaled by the learning rate
+    Sgd,
+    /// Step by the sign of the gradient only, each weight adapting its own step that starts at the learning rate
+    Rprop,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum InitMethod {
+    Uniform,
+    Normal,
+    /// He for ReLU-like activations, Xavier for the rest
+    Auto,
+}
+
 #[derive(Debug, Subcommand)]
 pub enum Commands {
     #[command(
@@ -25,6 +135,306 @@ Each line of input creates one line of output."
     Propagate {
         #[arg(help = "JSON file containing network structure, weights, and biases")]
         config_json_path: PathBuf,
+        #[command(flatten)]
+        load: LoadArgs,
+        #[arg(
+            long,
+            help = "Print the loaded configuration with every default filled in and exit without propagating"
+        )]
+        print_config: bool,
+        #[arg(
+            long,
+            help = "Repeat propagation of each input until the max output change drops below this tolerance"
+        )]
+        converge_tol: Option<f64>,
+        #[arg(
+            long,
+            default_value_t = 100,
+            requires = "converge_tol",
+            help = "Maximum number of propagations per input when converging (default: 100)"
+        )]
+        max_iterations: u32,
+        #[arg(
+            long,
+            help = "Expect the expected outputs appended to each input line and print the loss gradient magnitude at each input"
+        )]
+        saliency: bool,
+        #[arg(
+            long,
+            help = "Read '<input values...> | <expected outputs...>' lines and print the loss of each sample, --saliency then uses these expected outputs"
+        )]
+        expected: bool,
+        #[arg(long, value_enum, default_value_t = Loss::Squared, help = "Loss used by --expected and --saliency")]
+        loss: Loss,
+        #[arg(
+            long,
+            value_name = "E",
+            default_value_t = 1e-12,
+            help = "Cross-entropy clamps the outputs into [E, 1 - E] so confident wrong outputs give finite gradients (default: 1e-12)"
+        )]
+        loss_epsilon: f64,
+        #[arg(
+            long,
+            help = "After the last input print each neuron's activation statistics and histogram, flagging neurons stuck at the extremes of their activation"
+        )]
+        histogram_activations: bool,
+        #[arg(
+            long,
+            help = "After the last input print the mean, standard deviation, min and max of every output over all lines"
+        )]
+        stats: bool,
+        #[arg(
+            long,
+            help = "Treat the first token of each line as a sample id that is not fed to the network and prefix its output line with it"
+        )]
+        id_column: bool,
+        #[arg(
+            long,
+            default_value = " ",
+            help = "String put between the fields of an output line, e.g. $'\\t' for tab separated output"
+        )]
+        separator: String,
+        #[arg(
+            long,
+            num_args = 2,
+            value_names = ["MIN", "MAX"],
+            allow_negative_numbers = true,
+            help = "Clamp every printed output value into [MIN, MAX]"
+        )]
+        clip_outputs: Option<Vec<f64>>,
+        #[arg(
+            long,
+            conflicts_with = "clip_outputs",
+            help = "Print the index of the output with the highest activation instead of all outputs, ties go to the lowest index"
+        )]
+        argmax: bool,
+        #[arg(
+            long,
+            value_name = "K",
+            conflicts_with_all = ["clip_outputs", "argmax"],
+            help = "Print the K highest outputs as id:value pairs, sorted descending"
+        )]
+        top_k: Option<usize>,
+        #[arg(
+            long,
+            value_delimiter = ',',
+            value_name = "IDS",
+            conflicts_with_all = ["argmax", "top_k"],
+            help = "Print the activations of these comma separated neurons instead of the outputs, e.g. a hidden layer as features"
+        )]
+        output_neurons: Option<Vec<String>>,
+        #[arg(
+            long,
+            help = "After the outputs of each line, print every neuron's weighted inputs, bias, pre-activation, activation and output in propagation order"
+        )]
+        explain: bool,
+        #[arg(
+            long,
+            value_name = "OLD=NEW",
+            help = "Give every neuron using activation OLD activation NEW after loading, keeping the weights, e.g. ReLU=LeakyReLU. Can be repeated"
+        )]
+        activation_override: Vec<String>,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = FloatFormat::Shortest,
+            help = "Notation of the printed output, loss and saliency values"
+        )]
+        float_format: FloatFormat,
+        #[arg(long, help = "Read the input lines from this file instead of stdin")]
+        data: Option<PathBuf>,
+        #[arg(long, help = "Write the results to this file instead of stdout, log messages still go to stderr")]
+        output: Option<PathBuf>,
+        #[arg(
+            long,
+            value_name = "N",
+            value_parser = clap::value_parser!(u32).range(1..),
+            conflicts_with_all = ["converge_tol", "histogram_activations", "stats"],
+            help = "Read N lines at a time and propagate them in parallel, keeping their order (feed-forward networks only)"
+        )]
+        batch_size: Option<u32>,
+    },
+    #[command(
+        arg_required_else_help = true,
+        about = "Compute the Jacobian of the outputs with respect to the inputs.
+Reads space-separated input values from stdin and prints one row per output,
+each row holding d(output)/d(input) for every input. Matrices are separated by an empty line."
+    )]
+    Jacobian {
+        #[arg(help = "JSON file containing network structure, weights, and biases")]
+        config_json_path: PathBuf,
+    },
+    #[command(
+        arg_required_else_help = true,
+        about = "Trace every neuron's values while propagating.
+Reads space-separated input values from stdin and prints one line per depth,
+each entry being 'id:preactivation->activation'. Traces are separated by an empty line."
+    )]
+    Trace {
+        #[arg(help = "JSON file containing network structure, weights, and biases")]
+        config_json_path: PathBuf,
+        #[arg(
+            long,
+            value_name = "N",
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "Propagate each line N times and print every neuron's activation after each propagation as 'id: a1 a2 ... aN'"
+        )]
+        iterations: Option<u32>,
+    },
+    #[command(
+        arg_required_else_help = true,
+        about = "Describe the structure of a neural network.
+Prints the number of inputs, outputs, neurons and synapses."
+    )]
+    Describe {
+        #[arg(help = "JSON file containing network structure, weights, and biases")]
+        config_json_path: PathBuf,
+        #[arg(long, help = "Print an estimate of the memory used by the loaded network")]
+        mem_report: bool,
+        #[arg(
+            long,
+            help = "Print the number of parameters (weights and biases) and how many of them are trainable"
+        )]
+        count_params: bool,
+        #[arg(long, help = "Print the depth of every neuron, inputs are at depth 0")]
+        show_depths: bool,
+        #[arg(
+            long,
+            value_name = "PAIRS_FILE",
+            help = "Check that tied synapses are equal, the file holds one 'SOURCE->TARGET SOURCE->TARGET' pair per line. Fails if any pair differs by more than --symmetry-tol"
+        )]
+        check_symmetry: Option<PathBuf>,
+        #[arg(
+            long,
+            default_value_t = 1e-9,
+            requires = "check_symmetry",
+            help = "Largest accepted difference between tied weights (default: 1e-9)"
+        )]
+        symmetry_tol: f64,
+    },
+    #[command(
+        arg_required_else_help = true,
+        about = "Print the weights of a network as an ASCII heatmap.
+Rows are the neurons and columns the sources of their synapses, both ordered by depth.
+Each cell shows the absolute weight relative to the largest one, from '.' to '#', missing synapses are blank."
+    )]
+    Heatmap {
+        #[arg(help = "JSON file containing network structure, weights, and biases")]
+        config_json_path: PathBuf,
+    },
+    #[command(
+        arg_required_else_help = true,
+        about = "Initialize the missing weights of a network.
+Every synapse with a null weight is filled with a random value drawn from the chosen distribution.",
+        allow_missing_positional = true
+    )]
+    Init {
+        #[arg(
+            required_unless_present = "from_layers",
+            help = "JSON file containing the network structure, missing weights set to null"
+        )]
+        config_json_path: Option<PathBuf>,
+        #[arg(help = "Output file to save the initialized network configuration")]
+        save_config_json_path: PathBuf,
+        #[command(flatten)]
+        save: SaveArgs,
+        #[arg(
+            long,
+            value_delimiter = ',',
+            value_name = "SIZES",
+            conflicts_with = "config_json_path",
+            help = "Generate a fully connected network with these comma separated layer sizes instead of reading one, e.g. 3,5,5,2"
+        )]
+        from_layers: Option<Vec<usize>>,
+        #[arg(
+            long,
+            requires = "from_layers",
+            conflicts_with = "config_json_path",
+            help = "Activation of the hidden neurons generated by --from-layers, outputs are Linear (default: ReLU)"
+        )]
+        layer_activation: Option<String>,
+        #[arg(long, value_enum, default_value_t = InitMethod::Uniform, help = "Distribution to draw the weights from")]
+        init: InitMethod,
+        #[arg(
+            long,
+            num_args = 2,
+            value_names = ["LOW", "HIGH"],
+            allow_negative_numbers = true,
+            default_values_t = [-1.0, 1.0],
+            help = "Range of the uniform distribution"
+        )]
+        init_range: Vec<f64>,
+        #[arg(long, default_value_t = 0.0, allow_negative_numbers = true, help = "Mean of the normal distribution")]
+        init_mean: f64,
+        #[arg(
+            long,
+            default_value_t = 1.0,
+            allow_negative_numbers = true,
+            help = "Standard deviation of the normal distribution"
+        )]
+        init_std: f64,
+        #[arg(long, help = "Also draw every neuron bias from the distribution")]
+        init_biases: bool,
+        #[arg(long, help = "Seed for the random number generator")]
+        seed: Option<u64>,
+    },
+    #[command(
+        arg_required_else_help = true,
+        about = "Export a feed-forward network to ONNX.
+The network must not contain recurrent synapses and may only use the Linear, Identity, ReLU, SoftStep and TanH activations.
+The model takes an 'input' tensor of shape [batch, inputs] and produces an 'output' tensor of shape [batch, outputs]."
+    )]
+    ExportOnnx {
+        #[arg(help = "JSON file containing network structure, weights, and biases")]
+        config_json_path: PathBuf,
+        #[arg(help = "Output file for the ONNX model")]
+        out_path: PathBuf,
+    },
+    #[command(
+        arg_required_else_help = true,
+        about = "Export the weights and biases as NumPy arrays.
+Writes an .npz archive with three arrays per neuron, in neuron id order: '<id>.bias', '<id>.weights' and '<id>.sources',
+the id of the source of every weight. The weights are sorted by source id."
+    )]
+    ExportNpz {
+        #[arg(help = "JSON file containing network structure, weights, and biases")]
+        config_json_path: PathBuf,
+        #[arg(help = "Output file for the .npz archive")]
+        out_path: PathBuf,
+    },
+    #[command(
+        arg_required_else_help = true,
+        about = "Transform a network without changing the function it computes."
+    )]
+    Transform {
+        #[arg(help = "JSON file containing network structure, weights, and biases")]
+        config_json_path: PathBuf,
+        #[arg(help = "Output file to save the transformed network configuration")]
+        save_config_json_path: PathBuf,
+        #[command(flatten)]
+        save: SaveArgs,
+        #[arg(
+            long,
+            help = "Scale the incoming weights of every hidden neuron to unit L2 norm, compensating in the downstream weights"
+        )]
+        normalize_weights: bool,
+    },
+    #[command(
+        arg_required_else_help = true,
+        about = "Add Gaussian noise to every weight and bias of a network.
+Residual synapses are left untouched and weights shared within a layer stay shared."
+    )]
+    Perturb {
+        #[arg(help = "JSON file containing network structure, weights, and biases")]
+        config_json_path: PathBuf,
+        #[arg(help = "Output file to save the perturbed network configuration")]
+        save_config_json_path: PathBuf,
+        #[command(flatten)]
+        save: SaveArgs,
+        #[arg(long, allow_negative_numbers = true, help = "Standard deviation of the zero mean noise")]
+        std: f64,
+        #[arg(long, help = "Seed for the random number generator")]
+        seed: Option<u64>,
     },
     #[command(
         arg_required_else_help = true,
@@ -36,13 +446,216 @@ Training continues until EOF or SIGTERM signal."
     Learn {
         #[arg(help = "JSON file containing initial network structure and weights")]
         config_json_path: PathBuf,
-        #[arg(help = "Output file to save the trained network configuration")]
+        #[arg(help = "Output file to save the trained network configuration (may be the same as the input config)")]
         save_config_json_path: PathBuf,
+        #[command(flatten)]
+        save: SaveArgs,
+        #[command(flatten)]
+        load: LoadArgs,
+        #[arg(
+            long,
+            help = "Print the loaded configuration with every default filled in and exit without training"
+        )]
+        print_config: bool,
         #[arg(
             long,
             default_value_t = 1.0,
             help = "Learning rate controlling step size during training (default: 1.0)"
         )]
         learning_rate: f64,
+        #[arg(long, value_enum, default_value_t = Loss::Squared, help = "Loss minimized by the training, summed over the outputs")]
+        loss: Loss,
+        #[arg(
+            long,
+            value_name = "E",
+            default_value_t = 1e-12,
+            help = "Cross-entropy clamps the outputs into [E, 1 - E] so confident wrong outputs give finite gradients (default: 1e-12)"
+        )]
+        loss_epsilon: f64,
+        #[arg(
+            long,
+            default_value_t = 1.0,
+            help = "Multiply the loss derivative seeding the backpropagation by this factor, scaling every update like the learning rate does (default: 1.0)"
+        )]
+        loss_scale: f64,
+        #[arg(long, value_enum, default_value_t = OptimizerMethod::Sgd, help = "How the gradients update the weights and biases")]
+        optimizer: OptimizerMethod,
+        #[arg(
+            long,
+            help = "Carry on with the optimizer state saved in the config, e.g. the RProp steps, instead of starting it over"
+        )]
+        resume: bool,
+        #[arg(
+            long,
+            value_name = "N",
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "Accumulate the gradients of N samples and update the weights once per batch (default: 1)"
+        )]
+        batch_size: Option<u32>,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = GradReduction::Mean,
+            help = "How the gradients of a batch are combined before the update"
+        )]
+        grad_reduction: GradReduction,
+        #[arg(long, help = "Keep every bias as it is and only train the weights, e.g. for fine-tuning")]
+        freeze_biases: bool,
+        #[arg(
+            long,
+            value_name = "ALPHA",
+            default_value_t = 0.0,
+            help = "Train on (1-ALPHA)*expected + ALPHA/outputs instead of the expected outputs, softening one-hot targets (default: 0.0)"
+        )]
+        label_smoothing: f64,
+        #[arg(
+            long,
+            value_name = "RATE",
+            default_value_t = 0.0,
+            help = "Drop each synapse with this probability in every training step and scale the kept ones by 1/(1-RATE), validation uses all of them (default: 0.0)"
+        )]
+        dropconnect: f64,
+        #[arg(
+            long,
+            value_name = "D",
+            help = "Keep an exponential moving average of the weights and biases, updated as D*average + (1-D)*current after every training step"
+        )]
+        ema_decay: Option<f64>,
+        #[arg(long, requires = "ema_decay", help = "Save the averaged weights and biases instead of the trained ones")]
+        save_ema: bool,
+        #[arg(
+            long,
+            value_name = "STEP",
+            value_parser = clap::value_parser!(u64).range(1..),
+            conflicts_with = "save_ema",
+            help = "Stochastic weight averaging: from this training step on, average the weights and biases every --swa-freq steps and save the average"
+        )]
+        swa_start: Option<u64>,
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = 1,
+            value_parser = clap::value_parser!(u64).range(1..),
+            requires = "swa_start",
+            help = "Number of training steps between two models added to the SWA average (default: 1)"
+        )]
+        swa_freq: u64,
+        #[arg(
+            long,
+            requires = "val_file",
+            help = "Report the average validation error every N training steps"
+        )]
+        val_every: Option<u64>,
+        #[arg(
+            long,
+            requires = "val_every",
+            help = "Validation samples, input values and expected outputs on alternating lines like the training data"
+        )]
+        val_file: Option<PathBuf>,
+        #[arg(
+            long,
+            default_value_t = 1,
+            help = "Number of samples buffered and drawn from at random before training (default: 1, in order)"
+        )]
+        shuffle_buffer: usize,
+        #[arg(long, help = "Seed for the random number generator")]
+        seed: Option<u64>,
+        #[arg(
+            long,
+            help = "Print the accumulated error of every neuron, grouped by depth, after each training step"
+        )]
+        trace_gradients: bool,
+        #[arg(
+            long,
+            help = "Stop training after this many steps, showing a progress bar on an interactive stderr"
+        )]
+        max_steps: Option<u64>,
+        #[arg(
+            long,
+            value_name = "SECONDS",
+            help = "Stop training and save the network once this much wall-clock time has passed"
+        )]
+        time_limit: Option<f64>,
+        #[arg(long, group = "data_files", help = "Read the training samples from this file instead of stdin")]
+        data: Option<PathBuf>,
+        #[arg(
+            long,
+            value_name = "FILE",
+            group = "data_files",
+            requires = "expected",
+            help = "Read the input values of the training samples from this file, one sample per line, instead of stdin"
+        )]
+        inputs: Option<PathBuf>,
+        #[arg(
+            long,
+            value_name = "FILE",
+            requires = "inputs",
+            help = "Read the expected outputs of the training samples from this file, line by line along with --inputs"
+        )]
+        expected: Option<PathBuf>,
+        #[arg(
+            long,
+            requires = "data_files",
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "Number of passes over the --data or --inputs files, stdin can only be read once (default: 1)"
+        )]
+        epochs: Option<u32>,
+        #[arg(
+            long,
+            help = "Restore the previous weights and skip the update whenever a training step leaves a weight or bias non-finite"
+        )]
+        nan_guard: bool,
+    },
+    #[command(
+        arg_required_else_help = true,
+        about = "Check the backpropagated gradients against finite differences.
+Propagates random inputs in [-1, 1], compares the gradient of every bias and weight for random expected outputs
+with a central difference of the loss and prints the worst one. Fails if it is off by more than the tolerance."
+    )]
+    SelfTest {
+        #[arg(help = "JSON file containing network structure, weights, and biases")]
+        config_json_path: PathBuf,
+        #[command(flatten)]
+        load: LoadArgs,
+        #[arg(long, default_value_t = 10, help = "Number of random samples to check (default: 10)")]
+        samples: u32,
+        #[arg(long, default_value_t = 1e-6, help = "Step of the central differences (default: 1e-6)")]
+        epsilon: f64,
+        #[arg(
+            long,
+            default_value_t = 1e-4,
+            help = "Largest accepted difference, relative for gradients larger than 1 (default: 1e-4)"
+        )]
+        tolerance: f64,
+        #[arg(long, value_enum, default_value_t = Loss::Squared, help = "Loss whose gradients are checked")]
+        loss: Loss,
+        #[arg(
+            long,
+            value_name = "E",
+            default_value_t = 1e-12,
+            help = "Cross-entropy clamps the outputs into [E, 1 - E] so confident wrong outputs give finite gradients (default: 1e-12)"
+        )]
+        loss_epsilon: f64,
+        #[arg(long, help = "Seed for the random number generator")]
+        seed: Option<u64>,
+    },
+    #[command(
+        arg_required_else_help = true,
+        about = "Compare the outputs of a network with reference outputs.
+Propagates every line of the inputs file like propagate does and compares the outputs with the same line of the
+expected outputs file, e.g. one written by propagate. Prints the number of mismatches and the largest deviation
+and fails if any output deviates by more than the tolerance."
+    )]
+    Regress {
+        #[arg(help = "JSON file containing network structure, weights, and biases")]
+        config_json_path: PathBuf,
+        #[arg(help = "File with one line of input values per sample")]
+        inputs_file: PathBuf,
+        #[arg(help = "File with one line of reference output values per sample")]
+        expected_outputs_file: PathBuf,
+        #[command(flatten)]
+        load: LoadArgs,
+        #[arg(long, default_value_t = 1e-6, help = "Largest accepted absolute deviation of an output (default: 1e-6)")]
+        tol: f64,
     },
 }
@@ -2,11 +2,28 @@ use std::error::Error;
 use std::fmt;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum NeuralError {
     NetworkError(String),
     NeuronError(String),
     IoError(std::io::Error),
     ParseError(String),
+    // what was being done when the wrapped error happened
+    ContextError(String, Box<NeuralError>),
+}
+
+impl NeuralError {
+    pub fn context<S: Into<String>>(self, context: S) -> NeuralError {
+        NeuralError::ContextError(context.into(), Box::new(self))
+    }
+
+    // the innermost error, without any context
+    pub fn root_cause(&self) -> &NeuralError {
+        match self {
+            NeuralError::ContextError(_, source) => source.root_cause(),
+            _ => self,
+        }
+    }
 }
 
 impl fmt::Display for NeuralError {
@@ -16,11 +33,20 @@ impl fmt::Display for NeuralError {
             NeuralError::NeuronError(msg) => write!(f, "Neuron error: {}", msg),
             NeuralError::IoError(e) => write!(f, "IO error: {}", e),
             NeuralError::ParseError(msg) => write!(f, "Parse error: {}", msg),
+            NeuralError::ContextError(context, source) => write!(f, "{}: {}", context, source),
         }
     }
 }
 
-impl Error for NeuralError {}
+impl Error for NeuralError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NeuralError::IoError(e) => Some(e),
+            NeuralError::ContextError(_, source) => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl From<std::io::Error> for NeuralError {
     fn from(err: std::io::Error) -> NeuralError {
@@ -0,0 +1,9 @@
+#[cfg(not(feature = "single-precision"))]
+pub type Float = f64;
+#[cfg(feature = "single-precision")]
+pub type Float = f32;
+
+#[cfg(not(feature = "single-precision"))]
+pub use std::f64::consts;
+#[cfg(feature = "single-precision")]
+pub use std::f32::consts;
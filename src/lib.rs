@@ -0,0 +1,13 @@
+pub mod error;
+pub mod float;
+pub mod network;
+pub mod neurons;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use error::NeuralError;
+pub use float::Float;
+pub use network::builder::NeuralNetworkBuilder;
+pub use network::gradients::Gradients;
+pub use network::inference::InferenceModel;
+pub use network::{ConfigSnapshot, LoadOptions, NeuralNetwork};
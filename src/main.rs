@@ -1,110 +1,1299 @@
 mod cli;
-mod error;
-mod network;
-mod neurons;
 
-use cli::{Cli, Commands};
-use network::NeuralNetwork;
+use cli::{Cli, Commands, FloatFormat, GradReduction, InitMethod, LoadArgs, Loss, OptimizerMethod, SaveArgs};
+use mmnn::network::gradient_check::ParameterCheck;
+use mmnn::network::histogram::ActivationHistograms;
+use mmnn::network::statistics::OutputStatistics;
+use mmnn::network::initializer::Initializer;
+use mmnn::network::loss_function::LossFunction;
+use mmnn::network::npz;
+use mmnn::network::onnx;
+use mmnn::neurons::Optimizer;
+use mmnn::{Gradients, LoadOptions, NeuralError, NeuralNetwork};
 use std::fs;
-use std::io::{self, BufRead};
-use std::sync::{Arc, Mutex};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use clap::Parser;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use indicatif::{ProgressBar, ProgressStyle};
+use rand::rngs::StdRng;
+use rand::{RngExt, SeedableRng};
 
-use crate::error::NeuralError;
+use mmnn::Float;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+type Sample = (Vec<Vec<Float>>, Vec<Float>);
+
+fn parse_values(line: &str) -> Result<Vec<Float>, NeuralError> {
+    line.split_whitespace()
+        .map(|x| x.parse::<Float>())
+        .collect::<Result<Vec<Float>, _>>()
+        .map_err(|e| NeuralError::ParseError(e.to_string()))
+}
+
+// '_' or NaN marks an output without a label, it is left out of the loss and its gradient
+fn parse_expected(line: &str) -> Result<Vec<Float>, NeuralError> {
+    line.split_whitespace()
+        .map(|x| match x {
+            "_" => Ok(Float::NAN),
+            _ => x.parse::<Float>(),
+        })
+        .collect::<Result<Vec<Float>, _>>()
+        .map_err(|e| NeuralError::ParseError(e.to_string()))
+}
+
+fn parse_input_groups(line: &str) -> Result<Vec<Vec<Float>>, NeuralError> {
+    line.split(';').map(parse_values).collect()
+}
+
+fn parse_sample(line: &str) -> Result<Sample, NeuralError> {
+    let (inputs, expected) = line.split_once('|').ok_or_else(|| {
+        NeuralError::ParseError(format!("Expected '<inputs> | <expected outputs>', got '{}'", line))
+    })?;
+    Ok((parse_input_groups(inputs)?, parse_expected(expected)?))
+}
+
+// same layout as the training data, so a file can be used for either
+fn read_samples(path: &Path) -> Result<Vec<Sample>, NeuralError> {
+    interleaved_samples(open_data(Some(path))?).collect()
+}
+
+fn validation_error(nn: &mut NeuralNetwork, samples: &[Sample]) -> Result<Float, String> {
+    let mut total_error = 0.0;
+    nn.clear_dropconnect();
+    for (inputs, expected) in samples {
+        let inputs = nn.group_inputs(inputs)?;
+        nn.propagate(&inputs)?;
+        total_error += nn.get_error(expected)?;
+    }
+    Ok(total_error / samples.len().max(1) as Float)
+}
+
+fn load_options(args: &LoadArgs) -> LoadOptions {
+    LoadOptions {
+        max_depth: args.max_depth,
+        max_fan_in: args.max_fan_in,
+        strict_activation_names: args.strict_activation_names,
+        verify_depths: args.verify_depths,
+        strict: args.strict,
+        clamp_preactivation: args.clamp_preact.map(|max| max as Float),
+    }
+}
+
+fn loss_function(loss: Loss, epsilon: f64) -> Result<LossFunction, NeuralError> {
+    if !(epsilon > 0.0 && epsilon < 0.5) {
+        return Err(NeuralError::ParseError("--loss-epsilon E must be in (0, 0.5)".to_string()));
+    }
+    Ok(match loss {
+        Loss::Squared => LossFunction::LossSquared,
+        Loss::HalfSquared => LossFunction::LossHalfSquared,
+        Loss::Absolute => LossFunction::LossAbsolute,
+        Loss::CrossEntropy => LossFunction::LossCrossEntropy(epsilon as Float),
+    })
+}
+
+fn optimizer(method: OptimizerMethod) -> Optimizer {
+    match method {
+        OptimizerMethod::Sgd => Optimizer::Sgd,
+        OptimizerMethod::Rprop => Optimizer::RProp,
+    }
+}
+
+fn create_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => rand::make_rng(),
+    }
+}
+
+fn train_step(nn: &mut NeuralNetwork, (inputs, expected): Sample, learning_rate: Float, trace_gradients: bool) -> Option<Float> {
+    if let Err(msg) = nn.group_inputs(&inputs).and_then(|inputs| nn.propagate(&inputs)) {
+        log::error!("Propagation failed with message: '{}'", msg);
+        return None;
+    }
+    nn.print_outputs(true, true, None);
+    match nn.backpropagate(&expected, learning_rate) {
+        Ok(error) => {
+            if trace_gradients {
+                nn.print_error_map();
+            }
+            Some(error)
+        }
+        Err(msg) => {
+            log::error!("Backpropagation failed with message: '{}'", msg);
+            None
+        }
+    }
+}
+
+// the batch's gradients as --grad-reduction combines them
+fn reduce_gradients(gradients: &mut Gradients, reduction: GradReduction, samples: usize) {
+    if let GradReduction::Mean = reduction {
+        gradients.scale(1.0 / samples as Float);
+    }
+}
+
+// like train_step, but adds the gradients to the batch instead of applying them
+fn accumulate_step(
+    nn: &mut NeuralNetwork,
+    (inputs, expected): Sample,
+    batch: &mut Gradients,
+    trace_gradients: bool,
+) -> Option<Float> {
+    if let Err(msg) = nn.group_inputs(&inputs).and_then(|inputs| nn.propagate(&inputs)) {
+        log::error!("Propagation failed with message: '{}'", msg);
+        return None;
+    }
+    nn.print_outputs(true, true, None);
+    match nn.compute_gradients(&expected) {
+        Ok((error, gradients)) => {
+            log::info!("[Error: {}]", error);
+            if trace_gradients {
+                nn.print_error_map();
+            }
+            batch.accumulate(&gradients);
+            Some(error)
+        }
+        Err(msg) => {
+            log::error!("Backpropagation failed with message: '{}'", msg);
+            None
+        }
+    }
+}
+
+// 'SOURCE->TARGET' into the weight of that synapse
+fn synapse_weight(nn: &NeuralNetwork, synapse: &str) -> Result<Float, NeuralError> {
+    let (source, target) = synapse
+        .split_once("->")
+        .ok_or_else(|| NeuralError::ParseError(format!("Expected a synapse as 'SOURCE->TARGET', got '{}'", synapse)))?;
+    nn.get_synapse_weight(source, target)
+}
+
+fn check_symmetry(nn: &NeuralNetwork, pairs_file: &Path, tolerance: Float) -> Result<(), NeuralError> {
+    let reader = BufReader::new(fs::File::open(pairs_file)?);
+    let mut pairs = 0;
+    let mut mismatches = 0;
+    for line in reader.lines() {
+        let line = line?;
+        let synapses: Vec<&str> = line.split_whitespace().collect();
+        let [first, second] = synapses[..] else {
+            if synapses.is_empty() {
+                continue;
+            }
+            return Err(NeuralError::ParseError(format!("Expected two tied synapses per line, got '{}'", line)));
+        };
+        let (first_weight, second_weight) = (synapse_weight(nn, first)?, synapse_weight(nn, second)?);
+        pairs += 1;
+        let difference = (first_weight - second_weight).abs();
+        if difference.is_nan() || difference > tolerance {
+            mismatches += 1;
+            println!("{} {} != {} {} (difference {})", first, first_weight, second, second_weight, difference);
+        }
+    }
+    println!("Tied pairs: {}, differing: {}", pairs, mismatches);
+    if mismatches > 0 {
+        return Err(NeuralError::NetworkError(format!(
+            "{} tied pairs differ by more than {}",
+            mismatches, tolerance
+        )));
+    }
+    Ok(())
+}
+
+fn suspended<R>(progress: Option<&ProgressBar>, f: impl FnOnce() -> R) -> R {
+    // keeps the progress bar from being torn by whatever f writes to the terminal
+    match progress {
+        Some(progress) => progress.suspend(f),
+        None => f(),
+    }
+}
+
+fn open_data(path: Option<&Path>) -> Result<Box<dyn BufRead>, NeuralError> {
+    Ok(match path {
+        Some(path) => Box::new(BufReader::new(fs::File::open(path)?)),
+        None => Box::new(io::stdin().lock()),
+    })
+}
+
+type Samples = Box<dyn Iterator<Item = Result<Sample, NeuralError>>>;
+
+// odd lines hold the inputs, even lines the expected outputs
+fn interleaved_samples(reader: Box<dyn BufRead>) -> Samples {
+    let mut lines = reader.lines();
+    Box::new(std::iter::from_fn(move || {
+        let inputs = match lines.next()?.map_err(NeuralError::from).and_then(|line| parse_input_groups(&line)) {
+            Ok(inputs) => inputs,
+            Err(e) => return Some(Err(e)),
+        };
+        let expected = lines.next()?;
+        Some(expected.map_err(NeuralError::from).and_then(|line| parse_expected(&line)).map(|expected| (inputs, expected)))
+    }))
+}
+
+// line n of the inputs file with line n of the expected outputs file
+fn aligned_samples(inputs: &Path, expected: &Path) -> Result<Samples, NeuralError> {
+    let inputs = BufReader::new(fs::File::open(inputs)?).lines();
+    let expected = BufReader::new(fs::File::open(expected)?).lines();
+    Ok(Box::new(inputs.zip(expected).map(|(inputs, expected)| {
+        Ok((parse_input_groups(&inputs?)?, parse_expected(&expected?)?))
+    })))
+}
+
+fn count_lines(path: &Path) -> Result<usize, NeuralError> {
+    let mut lines = 0;
+    for line in BufReader::new(fs::File::open(path)?).lines() {
+        line?;
+        lines += 1;
+    }
+    Ok(lines)
+}
+
+fn create_output(path: Option<&Path>) -> Result<Box<dyn Write>, NeuralError> {
+    Ok(match path {
+        Some(path) => Box::new(BufWriter::new(fs::File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    })
+}
+
+fn save_config(path: &Path, nn: NeuralNetwork, save: &SaveArgs) -> Result<(), NeuralError> {
+    let json = match save.compact {
+        true => nn.print_as_json_compact(),
+        false => nn.print_as_json(),
+    };
+    let data = match save.gzip {
+        true => {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(json.as_bytes())?;
+            encoder.finish()?
+        }
+        false => json.into_bytes(),
+    };
+    // write next to the target and rename so the config being trained is never left half written
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, data)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+fn settle(nn: &mut NeuralNetwork, values: &[Float], tolerance: Float, max_iterations: u32) -> Result<u32, String> {
+    let mut iterations = 0;
+    loop {
+        iterations += 1;
+        let delta = nn.propagate(values)?;
+        if delta < tolerance || iterations >= max_iterations {
+            return Ok(iterations);
+        }
+    }
+}
+
+struct PropagateOptions {
+    converge_tol: Option<Float>,
+    max_iterations: u32,
+    saliency: bool,
+    expected: bool,
+    histogram_activations: bool,
+    stats: bool,
+    id_column: bool,
+    separator: String,
+    clip: Option<(Float, Float)>,
+    argmax: bool,
+    top_k: Option<usize>,
+    // neuron indices printed instead of the outputs
+    output_neurons: Option<Vec<usize>>,
+    explain: bool,
+    float_format: FloatFormat,
+    strict: bool,
+    clamp_preactivation: Option<Float>,
+    loss_function: LossFunction,
+    loss_epsilon: Float,
+}
+
+fn format_float(value: Float, format: FloatFormat) -> String {
+    match format {
+        FloatFormat::Fixed => format!("{:.6}", value),
+        FloatFormat::Scientific => format!("{:e}", value),
+        FloatFormat::Shortest => value.to_string(),
+    }
+}
+
+fn propagate_line(
+    nn: &mut NeuralNetwork,
+    line: &str,
+    out: &mut dyn Write,
+    options: &PropagateOptions,
+    histograms: Option<&mut ActivationHistograms>,
+    stats: Option<&mut OutputStatistics>,
+) -> Result<(), NeuralError> {
+    // the id is echoed in front of the outputs and never reaches the network
+    let (id, line) = match options.id_column {
+        true => match line.trim_start().split_once(char::is_whitespace) {
+            Some((id, values)) => (Some(id), values),
+            None => (Some(line.trim()), ""),
+        },
+        false => (None, line),
+    };
+    let (mut groups, mut expected) = match options.expected {
+        true => parse_sample(line).map(|(groups, expected)| (groups, Some(expected)))?,
+        false => (parse_input_groups(line)?, None),
+    };
+    if options.saliency && expected.is_none() {
+        // without --expected the expected outputs are appended to the last group
+        let last = groups.len() - 1;
+        let split_at = groups[last].len().saturating_sub(nn.get_output_count());
+        expected = Some(groups[last].split_off(split_at));
+    }
+    let values = match nn.group_inputs(&groups) {
+        Ok(values) => values,
+        Err(e) => {
+            log::error!("{}", e);
+            return Ok(());
+        }
+    };
+
+    let iterations = match options.converge_tol {
+        Some(tolerance) => settle(nn, &values, tolerance, options.max_iterations).map(Some),
+        None => nn.propagate(&values).map(|_| None),
+    };
+    let iterations = match iterations {
+        Ok(iterations) => iterations,
+        Err(e) => {
+            log::error!("{}", e);
+            return Ok(());
+        }
+    };
+    if let Some(histograms) = histograms {
+        histograms.record(nn);
+    }
+    if let Some(stats) = stats {
+        stats.record(nn);
+    }
+    let loss = match expected.as_ref().filter(|_| options.expected).map(|expected| nn.get_error(expected)) {
+        Some(Err(e)) => {
+            log::error!("{}", e);
+            return Ok(());
+        }
+        Some(Ok(loss)) => Some(loss),
+        None => None,
+    };
+    let gradients = match expected.filter(|_| options.saliency).map(|expected| nn.input_gradients(&expected)) {
+        Some(Err(e)) => {
+            log::error!("{}", e);
+            return Ok(());
+        }
+        Some(Ok(gradients)) => Some(gradients),
+        None => None,
+    };
+
+    let mut fields: Vec<String> = id.map(str::to_string).into_iter().collect();
+    if options.argmax {
+        fields.extend(nn.output_argmax().map(|index| index.to_string()));
+    } else if let Some(k) = options.top_k {
+        fields.extend(
+            nn.top_outputs(k)
+                .into_iter()
+                .map(|(id, value)| format!("{}:{}", id, format_float(value, options.float_format))),
+        );
+    } else {
+        let values = match &options.output_neurons {
+            Some(indices) => {
+                let activations = nn.activations();
+                indices.iter().map(|&index| activations[index]).collect()
+            }
+            None => nn.outputs(),
+        };
+        fields.extend(values.into_iter().map(|value| {
+            let value = match options.clip {
+                Some((min, max)) => value.clamp(min, max),
+                None => value,
+            };
+            format_float(value, options.float_format)
+        }));
+    }
+    if let Some(iterations) = iterations {
+        fields.push(format!("[Iterations: {}]", iterations));
+    }
+    if let Some(loss) = loss {
+        fields.push(format!("[Loss: {}]", format_float(loss, options.float_format)));
+    }
+    if let Some(gradients) = gradients {
+        let gradients: Vec<String> = gradients.iter().map(|x| format_float(x.abs(), options.float_format)).collect();
+        fields.push(format!("[Saliency: {}]", gradients.join(" ")));
+    }
+    writeln!(out, "{}", fields.join(&options.separator))?;
+    if options.explain {
+        for line in nn.explain() {
+            writeln!(out, "  {}", line)?;
+        }
+    }
+    Ok(())
+}
+
+fn propagate_lines<R: BufRead>(
+    nn: &mut NeuralNetwork,
+    reader: R,
+    out: &mut dyn Write,
+    options: &PropagateOptions,
+) -> Result<(), NeuralError> {
+    let mut histograms = options.histogram_activations.then(|| ActivationHistograms::new(nn));
+    let mut stats = options.stats.then(|| OutputStatistics::new(nn));
+    for line in reader.lines() {
+        propagate_line(nn, &line?, out, options, histograms.as_mut(), stats.as_mut())?;
+    }
+    if let Some(histograms) = histograms {
+        histograms.write(out)?;
+    }
+    if let Some(stats) = stats {
+        stats.write(out)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+// Every worker thread loads its own copy of the network once and propagates a slice of each batch
+// on it, which only gives the same results as propagate_lines when no state carries over from one
+// line to the next. The network isn't Send, so the copies are built from the snapshot on the workers.
+fn propagate_batches<R: BufRead>(
+    nn: &NeuralNetwork,
+    reader: R,
+    out: &mut dyn Write,
+    options: &PropagateOptions,
+    batch_size: usize,
+) -> Result<(), NeuralError> {
+    if !nn.is_feed_forward() {
+        return Err(NeuralError::NetworkError(
+            "Batches can only be propagated in parallel through feed-forward networks".to_string(),
+        ));
+    }
+    let snapshot = nn.config_snapshot();
+    let workers = rayon::current_num_threads();
+    let mut lines = reader.lines();
+    thread::scope(|scope| {
+        let mut channels = vec![];
+        for _ in 0..workers {
+            let (chunk_sender, chunk_receiver) = mpsc::channel::<Vec<String>>();
+            let (output_sender, output_receiver) = mpsc::channel();
+            let snapshot = &snapshot;
+            scope.spawn(move || {
+                let mut nn = match NeuralNetwork::from_config_snapshot(snapshot) {
+                    Ok(nn) => nn,
+                    Err(e) => {
+                        let _ = output_sender.send((vec![], Err(e)));
+                        return;
+                    }
+                };
+                nn.set_strict_input_bounds(options.strict);
+                nn.set_clamp_preactivation(options.clamp_preactivation);
+                nn.set_loss_function(options.loss_function);
+                nn.set_loss_epsilon(options.loss_epsilon);
+                for chunk in chunk_receiver {
+                    // the lines before a failing one are still written, like propagate_lines does
+                    let mut output = vec![];
+                    let result = chunk
+                        .iter()
+                        .try_for_each(|line| propagate_line(&mut nn, line, &mut output, options, None, None));
+                    if output_sender.send((output, result)).is_err() {
+                        return;
+                    }
+                }
+            });
+            channels.push((chunk_sender, output_receiver));
+        }
+        loop {
+            let batch: Vec<String> = lines.by_ref().take(batch_size).collect::<Result<_, _>>()?;
+            if batch.is_empty() {
+                break;
+            }
+            let chunk_size = batch.len().div_ceil(workers);
+            let mut batch = batch.into_iter().peekable();
+            let mut busy = 0;
+            while batch.peek().is_some() {
+                // a worker that failed to load has already sent its error, which is received below
+                let _ = channels[busy].0.send(batch.by_ref().take(chunk_size).collect());
+                busy += 1;
+            }
+            for (_, output_receiver) in channels.iter().take(busy) {
+                let (output, result) = output_receiver
+                    .recv()
+                    .map_err(|_| NeuralError::NetworkError("A propagation worker stopped".to_string()))?;
+                out.write_all(&output)?;
+                result?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    })
+}
+
+fn init_logger(verbose: u8, deterministic_output: bool) {
+    let level = match verbose {
+        0 => "info",
+        1 => "debug",
+        _ => "trace",
+    };
+    let mut builder = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level));
+    builder.format_timestamp(None).format_target(false);
+    if deterministic_output {
+        // stdout is line buffered while stderr is not, flushing first keeps a partially printed
+        // output line ahead of the log message
+        builder.format(|buf, record| {
+            io::stdout().flush()?;
+            let style = buf.default_level_style(record.level());
+            writeln!(buf, "[{style}{:<5}{style:#}] {}", record.level(), record.args())
+        });
+    }
+    builder.init();
+}
+
+fn init_thread_pool(threads: Option<u32>) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(threads) = threads {
+        rayon::ThreadPoolBuilder::new().num_threads(threads as usize).build_global()?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
     let args = Cli::parse();
-    
+    init_logger(args.verbose, args.deterministic_output);
+    let verbose_errors = args.verbose_errors;
+    let Err(e) = run(args) else {
+        return ExitCode::SUCCESS;
+    };
+    // without --verbose-errors only the innermost error is shown, like before errors had context
+    match e.downcast_ref::<NeuralError>() {
+        Some(e) if !verbose_errors => eprintln!("Error: {:?}", e.root_cause()),
+        Some(e) => eprintln!("Error: {}", e),
+        None => eprintln!("Error: {:?}", e),
+    }
+    ExitCode::FAILURE
+}
+
+fn run(args: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    init_thread_pool(args.threads)?;
+
     match args.command {
-        Commands::Propagate { config_json_path } => {
+        Commands::Propagate {
+            config_json_path,
+            load,
+            print_config,
+            converge_tol,
+            max_iterations,
+            saliency,
+            expected,
+            loss,
+            loss_epsilon,
+            histogram_activations,
+            stats,
+            id_column,
+            separator,
+            clip_outputs,
+            argmax,
+            top_k,
+            output_neurons,
+            explain,
+            activation_override,
+            float_format,
+            data,
+            output,
+            batch_size,
+        } => {
+            let clip = match clip_outputs.as_deref() {
+                Some(&[min, max]) if min <= max => Some((min as Float, max as Float)),
+                Some(_) => return Err(NeuralError::ParseError("--clip-outputs MIN must not exceed MAX".to_string()).into()),
+                None => None,
+            };
+            let mut nn = NeuralNetwork::new_with_options(config_json_path, &load_options(&load))?;
+            for replacement in &activation_override {
+                let Some((old, new)) = replacement.split_once('=') else {
+                    return Err(NeuralError::ParseError(format!(
+                        "--activation-override expects OLD=NEW, got '{}'",
+                        replacement
+                    ))
+                    .into());
+                };
+                let replaced = nn.replace_activation(old.trim(), new.trim())?;
+                if replaced == 0 {
+                    log::warn!("No neuron uses activation '{}', nothing to override", old.trim());
+                }
+            }
+            let output_neurons = output_neurons.map(|ids| nn.neuron_indices(&ids)).transpose()?;
+            let options = PropagateOptions {
+                converge_tol: converge_tol.map(|tolerance| tolerance as Float),
+                max_iterations,
+                saliency,
+                expected,
+                histogram_activations,
+                stats,
+                id_column,
+                separator,
+                clip,
+                argmax,
+                top_k,
+                output_neurons,
+                explain,
+                float_format,
+                strict: load.strict,
+                clamp_preactivation: load.clamp_preact.map(|max| max as Float),
+                loss_function: loss_function(loss, loss_epsilon)?,
+                loss_epsilon: loss_epsilon as Float,
+            };
+            if print_config {
+                println!("{}", nn.print_as_json());
+                return Ok(());
+            }
+            nn.set_loss_function(options.loss_function);
+            nn.set_loss_epsilon(options.loss_epsilon);
+            let reader = open_data(data.as_deref())?;
+            let mut out = create_output(output.as_deref())?;
+            match batch_size {
+                Some(batch_size) => propagate_batches(&nn, reader, &mut out, &options, batch_size as usize)?,
+                None => propagate_lines(&mut nn, reader, &mut out, &options)?,
+            }
+        }
+        Commands::Jacobian { config_json_path } => {
+            let mut nn = NeuralNetwork::new(config_json_path)?;
+            let stdin = io::stdin();
+            for line in stdin.lock().lines() {
+                let groups = parse_input_groups(&line?)?;
+
+                if let Err(e) = nn.group_inputs(&groups).and_then(|values| nn.propagate(&values)) {
+                    log::error!("{}", e);
+                    continue;
+                }
+                for row in nn.jacobian() {
+                    let row: Vec<String> = row.iter().map(|x| x.to_string()).collect();
+                    println!("{}", row.join(" "));
+                }
+                println!();
+            }
+        }
+        Commands::Trace {
+            config_json_path,
+            iterations,
+        } => {
             let mut nn = NeuralNetwork::new(config_json_path)?;
             let stdin = io::stdin();
             for line in stdin.lock().lines() {
-                let line = line?;
-                let values: Result<Vec<f64>, _> = line
-                    .trim()
-                    .split_whitespace()
-                    .map(|x| x.parse::<f64>())
-                    .collect();
-                
-                let values = values.map_err(|e| NeuralError::ParseError(e.to_string()))?;
-                
-                if let Err(e) = nn.propagate(&values) {
-                    eprintln!("Error: {}", e);
+                let groups = parse_input_groups(&line?)?;
+                let values = match nn.group_inputs(&groups) {
+                    Ok(values) => values,
+                    Err(e) => {
+                        log::error!("{}", e);
+                        continue;
+                    }
+                };
+
+                let Some(iterations) = iterations else {
+                    if let Err(e) = nn.propagate(&values) {
+                        log::error!("{}", e);
+                        continue;
+                    }
+                    nn.print_trace();
+                    println!();
                     continue;
+                };
+                let mut history = Vec::with_capacity(iterations as usize);
+                for _ in 0..iterations {
+                    if let Err(e) = nn.propagate(&values) {
+                        log::error!("{}", e);
+                        break;
+                    }
+                    history.push(nn.activations());
                 }
-                nn.print_outputs(false, true);
+                nn.print_trajectories(&history);
+                println!();
+            }
+        }
+        Commands::Describe {
+            config_json_path,
+            mem_report,
+            count_params,
+            show_depths,
+            check_symmetry: pairs_file,
+            symmetry_tol,
+        } => {
+            let nn = NeuralNetwork::new(config_json_path)?;
+            nn.print_description();
+            if count_params {
+                nn.print_parameter_counts();
+            }
+            if show_depths {
+                nn.print_depths();
+            }
+            if mem_report {
+                nn.print_memory_report();
+            }
+            if let Some(pairs_file) = pairs_file {
+                check_symmetry(&nn, &pairs_file, symmetry_tol as Float)?;
             }
         }
+        Commands::Heatmap { config_json_path } => {
+            let nn = NeuralNetwork::new(config_json_path)?;
+            nn.print_heatmap();
+        }
+        Commands::Init {
+            config_json_path,
+            save_config_json_path,
+            save,
+            from_layers,
+            layer_activation,
+            init,
+            init_range,
+            init_mean,
+            init_std,
+            init_biases,
+            seed,
+        } => {
+            let initializer = match init {
+                InitMethod::Uniform => Some(Initializer::uniform(init_range[0] as Float, init_range[1] as Float)?),
+                InitMethod::Normal => Some(Initializer::normal(init_mean as Float, init_std as Float)?),
+                InitMethod::Auto => None,
+            };
+            let mut rng = create_rng(seed);
+            let nn = match (from_layers, config_json_path) {
+                (Some(layer_sizes), _) => NeuralNetwork::new_layered_initialized(
+                    &layer_sizes,
+                    layer_activation.as_deref().unwrap_or("ReLU"),
+                    initializer.as_ref(),
+                    init_biases,
+                    &mut rng,
+                )?,
+                (None, Some(config_json_path)) => {
+                    NeuralNetwork::new_initialized(config_json_path, initializer.as_ref(), init_biases, &mut rng)?
+                }
+                (None, None) => unreachable!("clap requires a config without --from-layers"),
+            };
+            save_config(&save_config_json_path, nn, &save)?;
+        }
+        Commands::ExportOnnx {
+            config_json_path,
+            out_path,
+        } => {
+            let nn = NeuralNetwork::new(config_json_path)?;
+            fs::write(out_path, onnx::export(&nn)?)?;
+        }
+        Commands::ExportNpz {
+            config_json_path,
+            out_path,
+        } => {
+            let nn = NeuralNetwork::new(config_json_path)?;
+            fs::write(out_path, npz::export(&nn)?)?;
+        }
+        Commands::Transform {
+            config_json_path,
+            save_config_json_path,
+            save,
+            normalize_weights,
+        } => {
+            let mut nn = NeuralNetwork::new(config_json_path)?;
+            if normalize_weights {
+                for (id, scale) in nn.normalize_weights()? {
+                    log::info!("[Normalized: {}, Scale: {}]", id, scale);
+                }
+            }
+            save_config(&save_config_json_path, nn, &save)?;
+        }
+        Commands::Perturb {
+            config_json_path,
+            save_config_json_path,
+            save,
+            std,
+            seed,
+        } => {
+            let noise = Initializer::normal(0.0, std as Float)?;
+            let mut nn = NeuralNetwork::new(config_json_path)?;
+            nn.perturb(&noise, &mut create_rng(seed));
+            save_config(&save_config_json_path, nn, &save)?;
+        }
         Commands::Learn {
             config_json_path,
             save_config_json_path,
+            save,
+            load,
+            print_config,
             learning_rate,
+            loss,
+            loss_epsilon,
+            loss_scale,
+            optimizer: optimizer_method,
+            resume,
+            batch_size,
+            grad_reduction,
+            freeze_biases,
+            label_smoothing,
+            dropconnect,
+            ema_decay,
+            save_ema,
+            swa_start,
+            swa_freq,
+            val_every,
+            val_file,
+            shuffle_buffer,
+            seed,
+            trace_gradients,
+            max_steps,
+            time_limit,
+            data,
+            inputs,
+            expected,
+            epochs,
+            nan_guard,
         } => {
-            let mut nn = NeuralNetwork::new(config_json_path)?;
-            let stdin = io::stdin();
-            let mut propagate = true;
+            if !(0.0..=1.0).contains(&label_smoothing) {
+                return Err(NeuralError::ParseError("--label-smoothing ALPHA must be in [0, 1]".to_string()).into());
+            }
+            if !(0.0..1.0).contains(&dropconnect) {
+                return Err(NeuralError::ParseError("--dropconnect RATE must be in [0, 1)".to_string()).into());
+            }
+            if ema_decay.is_some_and(|decay| !(0.0..=1.0).contains(&decay)) {
+                return Err(NeuralError::ParseError("--ema-decay D must be in [0, 1]".to_string()).into());
+            }
+            let deadline = time_limit
+                .map(|seconds| Duration::try_from_secs_f64(seconds).map(|limit| Instant::now() + limit))
+                .transpose()
+                .map_err(|e| NeuralError::ParseError(format!("Invalid --time-limit: {}", e)))?;
+            // checked once per line, which is cheap next to a training step
+            let timed_out = || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            if let (Some(inputs), Some(expected)) = (&inputs, &expected) {
+                let (input_lines, expected_lines) = (count_lines(inputs)?, count_lines(expected)?);
+                if input_lines != expected_lines {
+                    return Err(NeuralError::ParseError(format!(
+                        "--inputs has {} lines but --expected has {}",
+                        input_lines, expected_lines
+                    ))
+                    .into());
+                }
+            }
+            let mut nn = NeuralNetwork::new_with_options(config_json_path, &load_options(&load))?;
+            if print_config {
+                println!("{}", nn.print_as_json());
+                return Ok(());
+            }
+            nn.set_loss_function(loss_function(loss, loss_epsilon)?);
+            nn.set_loss_epsilon(loss_epsilon as Float);
+            nn.set_loss_scale(loss_scale as Float);
+            nn.set_optimizer(optimizer(optimizer_method));
+            if !resume {
+                nn.clear_optimizer_state();
+            }
+            nn.set_freeze_biases(freeze_biases);
+            nn.set_label_smoothing(label_smoothing as Float);
+            let validation_samples = match &val_file {
+                Some(path) => read_samples(path)?,
+                None => vec![],
+            };
+            let mut rng = create_rng(seed);
+            let shuffle_buffer_size = shuffle_buffer.max(1);
+            let mut shuffle_buffer: Vec<Sample> = Vec::with_capacity(shuffle_buffer_size);
+            let mut steps: u64 = 0;
+            let mut training_error = 0.0;
 
             let caught_sigterm: Arc<Mutex<bool>> = Arc::new(Mutex::new(false));
             let caught_sigterm_rc = Arc::clone(&caught_sigterm);
 
             ctrlc::set_handler(move || {
-                if *caught_sigterm_rc.lock().unwrap() == true {
-                    eprintln!("Goodbye!");
+                if *caught_sigterm_rc.lock().unwrap() {
+                    log::warn!("Goodbye!");
                     std::process::exit(1);
                 }
-                eprintln!("SIGTERM caught, exiting on next line iteration.");
+                log::warn!("SIGTERM caught, exiting on next line iteration.");
                 *caught_sigterm_rc.lock().unwrap() = true;
             })
             .expect("Error setting Ctrl-C handler");
 
-            for line in stdin.lock().lines() {
-                if *caught_sigterm.lock().unwrap() == true {
-                    break;
+            let progress = max_steps.filter(|_| io::stderr().is_terminal()).map(|max_steps| {
+                let progress = ProgressBar::new(max_steps);
+                progress.set_style(
+                    ProgressStyle::with_template("{bar:40} {pos}/{len} [{elapsed_precise}] {msg}")
+                        .expect("Invalid progress bar template"),
+                );
+                progress
+            });
+
+            // the moving average starts at the initial parameters
+            let mut average: Vec<Float> = vec![];
+            let mut current: Vec<Float> = vec![];
+            if ema_decay.is_some() {
+                nn.snapshot_parameters(&mut average);
+            }
+            // equal weight mean of the parameters after every --swa-freq-th step from --swa-start on
+            let mut swa_average: Vec<Float> = vec![];
+            let mut swa_models: u64 = 0;
+
+            // with batches a step is one update, the gradients and losses of its samples are summed here
+            let batch_size = batch_size.unwrap_or(1) as usize;
+            let mut batch = Gradients::default();
+            let mut batch_error = 0.0;
+            let mut batch_samples = 0;
+
+            // returns true once the maximum number of steps is reached, None applies a partial batch
+            let mut snapshot: Vec<Float> = vec![];
+            let mut train = |sample: Option<Sample>, rng: &mut StdRng| -> bool {
+                if dropconnect > 0.0 && sample.is_some() {
+                    nn.sample_dropconnect(dropconnect as Float, rng);
                 }
-                propagate = match propagate {
-                    true => {
-                        let values: Vec<f64> = line
-                            .unwrap()
-                            .trim()
-                            .split_whitespace()
-                            .map(|x| x.to_string().trim().parse::<f64>().unwrap())
-                            .collect();
-                        match nn.propagate(&values) {
-                            Ok(_) => {
-                                nn.print_outputs(true, false);
-                                false
-                            },
-                            Err(msg) => {
-                                eprintln!("Propagation failed with message: '{}'", msg);
-                                true
-                            }
+                let error = match sample {
+                    Some(sample) if batch_size == 1 => {
+                        if nan_guard {
+                            nn.snapshot_parameters(&mut snapshot);
                         }
+                        suspended(progress.as_ref(), || {
+                            train_step(&mut nn, sample, learning_rate as Float, trace_gradients)
+                        })
                     }
-                    false => {
-                        let values: Vec<f64> = line
-                            .unwrap()
-                            .trim()
-                            .split_whitespace()
-                            .map(|x| x.to_string().trim().parse::<f64>().unwrap())
-                            .collect();
-                        match nn.backpropagate(&values, learning_rate) {
-                            Ok(_) => {
-                                true
+                    sample => {
+                        if let Some(sample) = sample {
+                            let error = suspended(progress.as_ref(), || {
+                                accumulate_step(&mut nn, sample, &mut batch, trace_gradients)
+                            });
+                            let Some(error) = error else {
+                                return false;
+                            };
+                            batch_error += error;
+                            batch_samples += 1;
+                            if batch_samples < batch_size {
+                                return false;
                             }
+                        }
+                        if batch_samples == 0 {
+                            return false;
+                        }
+                        if nan_guard {
+                            nn.snapshot_parameters(&mut snapshot);
+                        }
+                        let mut gradients = std::mem::take(&mut batch);
+                        reduce_gradients(&mut gradients, grad_reduction, batch_samples);
+                        let error = std::mem::take(&mut batch_error) / batch_samples as Float;
+                        batch_samples = 0;
+                        match nn.apply_gradients(&gradients, learning_rate as Float) {
+                            Ok(()) => Some(error),
                             Err(msg) => {
-                                eprintln!("Backpropagation failed with message: '{}'", msg);
-                                false
+                                suspended(progress.as_ref(), || log::error!("Update failed with message: '{}'", msg));
+                                None
                             }
                         }
                     }
+                };
+                let Some(error) = error else {
+                    return false;
+                };
+                if nan_guard && nn.has_non_finite_parameters() {
+                    nn.restore_parameters(&snapshot);
+                    suspended(progress.as_ref(), || {
+                        log::warn!("Non-finite weights after the training step, the update was skipped")
+                    });
+                    return false;
+                }
+                if let Some(decay) = ema_decay {
+                    nn.update_average_parameters(&mut average, decay as Float);
+                }
+                steps += 1;
+                if swa_start.is_some_and(|start| steps >= start && (steps - start).is_multiple_of(swa_freq)) {
+                    // the running mean is a moving average whose decay shrinks with every model
+                    nn.update_average_parameters(&mut swa_average, swa_models as Float / (swa_models + 1) as Float);
+                    swa_models += 1;
+                }
+                training_error += error;
+                if let Some(progress) = &progress {
+                    progress.set_message(format!("error: {}", error));
+                    progress.inc(1);
+                }
+                if let Some(every) = val_every.filter(|&every| steps.is_multiple_of(every)) {
+                    let validation = validation_error(&mut nn, &validation_samples).and_then(|validation| {
+                        if ema_decay.is_none() {
+                            return Ok((validation, None));
+                        }
+                        // validated with the averaged parameters swapped in
+                        nn.snapshot_parameters(&mut current);
+                        nn.restore_parameters(&average);
+                        let ema_validation = validation_error(&mut nn, &validation_samples);
+                        nn.restore_parameters(&current);
+                        Ok((validation, Some(ema_validation?)))
+                    });
+                    suspended(progress.as_ref(), || match validation {
+                        Ok((validation, None)) => log::info!(
+                            "[Training error: {}, Validation error: {}]",
+                            training_error / every as Float,
+                            validation
+                        ),
+                        Ok((validation, Some(ema_validation))) => log::info!(
+                            "[Training error: {}, Validation error: {}, EMA validation error: {}]",
+                            training_error / every as Float,
+                            validation,
+                            ema_validation
+                        ),
+                        Err(msg) => log::error!("Validation failed with message: '{}'", msg),
+                    });
+                    training_error = 0.0;
+                }
+                max_steps.is_some_and(|max_steps| steps >= max_steps)
+            };
+
+            let epochs = epochs.unwrap_or(1);
+            'epochs: for epoch in 1..=epochs {
+                if epochs > 1 {
+                    log::debug!("Epoch {}/{}", epoch, epochs);
+                }
+                let samples = match (&inputs, &expected) {
+                    (Some(inputs), Some(expected)) => aligned_samples(inputs, expected)?,
+                    _ => interleaved_samples(open_data(data.as_deref())?),
+                };
+                for sample in samples {
+                    if *caught_sigterm.lock().unwrap() {
+                        break 'epochs;
+                    }
+                    if timed_out() {
+                        log::info!("Time limit reached, stopping the training");
+                        break 'epochs;
+                    }
+                    let sample = sample?;
+                    if shuffle_buffer.len() < shuffle_buffer_size {
+                        shuffle_buffer.push(sample);
+                        continue;
+                    }
+                    let slot = rng.random_range(0..shuffle_buffer.len());
+                    if train(Some(std::mem::replace(&mut shuffle_buffer[slot], sample)), &mut rng) {
+                        shuffle_buffer.clear();
+                        break 'epochs;
+                    }
                 }
             }
+            while !shuffle_buffer.is_empty() && !*caught_sigterm.lock().unwrap() && !timed_out() {
+                let slot = rng.random_range(0..shuffle_buffer.len());
+                if train(Some(shuffle_buffer.swap_remove(slot)), &mut rng) {
+                    break;
+                }
+            }
+            // the samples of an unfinished batch still update the weights
+            train(None, &mut rng);
+            if let Some(progress) = progress {
+                progress.finish();
+            }
 
-            let data = nn.print_as_json();
-            fs::write(save_config_json_path, data.as_str()).expect("Unable to write file");
+            if save_ema {
+                nn.restore_parameters(&average);
+            }
+            if swa_start.is_some() {
+                match swa_models {
+                    0 => log::warn!("Training ended before --swa-start, saving the trained weights"),
+                    _ => {
+                        log::info!("Saving the average of {} models", swa_models);
+                        nn.restore_parameters(&swa_average);
+                    }
+                }
+            }
+            save_config(&save_config_json_path, nn, &save)?;
+        }
+        Commands::SelfTest {
+            config_json_path,
+            load,
+            samples,
+            epsilon,
+            tolerance,
+            loss,
+            loss_epsilon,
+            seed,
+        } => {
+            let mut nn = NeuralNetwork::new_with_options(config_json_path, &load_options(&load))?;
+            nn.set_loss_function(loss_function(loss, loss_epsilon)?);
+            nn.set_loss_epsilon(loss_epsilon as Float);
+            let mut rng = create_rng(seed);
+            let mut checked = 0;
+            let mut worst: Option<ParameterCheck> = None;
+            for _ in 0..samples {
+                let inputs: Vec<Float> = (0..nn.get_input_count()).map(|_| rng.random_range(-1.0..=1.0)).collect();
+                let expected: Vec<Float> = (0..nn.get_output_count()).map(|_| rng.random_range(-1.0..=1.0)).collect();
+                let checks = nn.check_gradients(&inputs, &expected, epsilon as Float).map_err(NeuralError::NetworkError)?;
+                for check in checks {
+                    checked += 1;
+                    if worst.as_ref().is_none_or(|worst| check.error() > worst.error()) {
+                        worst = Some(check);
+                    }
+                }
+            }
+            let Some(worst) = worst else {
+                println!("No gradients to check");
+                return Ok(());
+            };
+            println!(
+                "Checked {} gradients on {} samples, worst is the {}: analytic {}, numeric {}, error {:e}",
+                checked,
+                samples,
+                worst.name(),
+                worst.analytic,
+                worst.numeric,
+                worst.error()
+            );
+            if worst.error() > tolerance as Float {
+                return Err(NeuralError::NetworkError(format!(
+                    "Gradient of the {} is off by {}, more than the tolerance of {}",
+                    worst.name(),
+                    worst.error(),
+                    tolerance
+                ))
+                .into());
+            }
+        }
+        Commands::Regress {
+            config_json_path,
+            inputs_file,
+            expected_outputs_file,
+            load,
+            tol,
+        } => {
+            let mut nn = NeuralNetwork::new_with_options(config_json_path, &load_options(&load))?;
+            let inputs = BufReader::new(fs::File::open(inputs_file)?).lines();
+            let mut expected = BufReader::new(fs::File::open(expected_outputs_file)?).lines();
+            let mut lines = 0;
+            let mut mismatches = 0;
+            // deviation, line and output id of the largest deviation
+            let mut worst: Option<(Float, usize, String)> = None;
+            for line in inputs {
+                lines += 1;
+                let Some(expected_line) = expected.next() else {
+                    return Err(NeuralError::ParseError(format!("Expected outputs end before input line {}", lines)).into());
+                };
+                nn.group_inputs(&parse_input_groups(&line?)?)
+                    .and_then(|values| nn.propagate(&values))
+                    .map_err(|e| NeuralError::NetworkError(format!("Line {}: {}", lines, e)))?;
+                let reference = parse_values(&expected_line?)?;
+                if reference.len() != nn.get_output_count() {
+                    return Err(NeuralError::ParseError(format!(
+                        "Line {}: {} expected outputs for {} outputs",
+                        lines,
+                        reference.len(),
+                        nn.get_output_count()
+                    ))
+                    .into());
+                }
+                for ((id, value), reference) in nn.outputs_named().into_iter().zip(reference) {
+                    let deviation = (value - reference).abs();
+                    // a NaN on either side is a mismatch unless both are NaN
+                    let deviation = match (value.is_nan(), reference.is_nan()) {
+                        (true, true) => 0.0,
+                        (false, false) => deviation,
+                        _ => Float::INFINITY,
+                    };
+                    if deviation > tol as Float {
+                        mismatches += 1;
+                        log::warn!("Line {}: output '{}' is {} instead of {}", lines, id, value, reference);
+                    }
+                    if worst.as_ref().is_none_or(|(max, _, _)| deviation > *max) {
+                        worst = Some((deviation, lines, id));
+                    }
+                }
+            }
+            if expected.next().is_some() {
+                return Err(NeuralError::ParseError(format!("Expected outputs have more than {} lines", lines)).into());
+            }
+            match &worst {
+                Some((deviation, line, id)) => println!(
+                    "Compared {} lines, {} outputs off by more than {}, largest deviation {} of output '{}' on line {}",
+                    lines, mismatches, tol, deviation, id, line
+                ),
+                None => println!("Compared {} lines, no outputs", lines),
+            }
+            if mismatches > 0 {
+                return Err(NeuralError::NetworkError(format!(
+                    "{} outputs deviate from the reference by more than {}",
+                    mismatches, tol
+                ))
+                .into());
+            }
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameters(nn: &NeuralNetwork) -> Vec<Float> {
+        nn.neurons_sorted()
+            .filter(|neuron| !neuron.is_input())
+            .flat_map(|neuron| {
+                let mut synapses: Vec<(String, Float)> = neuron.get_synapses_map().into_iter().collect();
+                synapses.sort_by(|a, b| a.0.cmp(&b.0));
+                std::iter::once(neuron.get_bias()).chain(synapses.into_iter().map(|(_, weight)| weight)).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn summed_gradients_step_like_the_mean_with_a_batch_sized_learning_rate() {
+        let config = r#"{"inputs": ["X", "Y"], "outputs": ["O"], "neurons": {
+            "H": {"activation": "TanH", "synapses": {"X": 0.8, "Y": -0.4}, "bias": 0.1},
+            "O": {"synapses": {"H": 1.5, "X": 0.3}}
+        }}"#;
+        let batch: Vec<Sample> = vec![
+            (vec![vec![0.5, -1.0]], vec![0.3]),
+            (vec![vec![-0.2, 0.7]], vec![-0.4]),
+            (vec![vec![1.0, 1.0]], vec![0.0]),
+        ];
+        let train = |reduction: GradReduction, learning_rate: Float| {
+            let mut nn: NeuralNetwork = config.parse().unwrap();
+            let mut gradients = Gradients::default();
+            for sample in batch.iter() {
+                accumulate_step(&mut nn, sample.clone(), &mut gradients, false).unwrap();
+            }
+            reduce_gradients(&mut gradients, reduction, batch.len());
+            nn.apply_gradients(&gradients, learning_rate).unwrap();
+            parameters(&nn)
+        };
+        let initial = parameters(&config.parse().unwrap());
+        let (sum, mean) = (train(GradReduction::Sum, 0.1), train(GradReduction::Mean, 0.3));
+        for ((sum, mean), initial) in sum.iter().zip(mean.iter()).zip(initial.iter()) {
+            assert!((sum - mean).abs() < 1e-6, "{} vs {}", sum, mean);
+            assert_ne!(sum, initial);
+        }
+        // the mean with the same learning rate takes a third of the step
+        let small = train(GradReduction::Mean, 0.1);
+        for ((sum, small), initial) in sum.iter().zip(small.iter()).zip(initial.iter()) {
+            assert!(((small - initial) * 3.0 - (sum - initial)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn batches_write_the_lines_before_a_failing_one_like_sequential_propagation() {
+        let config = r#"{"inputs": ["X"], "outputs": ["O"], "neurons": {
+            "H": {"activation": "TanH", "synapses": {"X": 0.8}, "bias": 0.1},
+            "O": {"synapses": {"H": 1.5}}
+        }}"#;
+        let options = PropagateOptions {
+            converge_tol: None,
+            max_iterations: 0,
+            saliency: false,
+            expected: false,
+            histogram_activations: false,
+            stats: false,
+            id_column: false,
+            separator: " ".to_string(),
+            clip: None,
+            argmax: false,
+            top_k: None,
+            output_neurons: None,
+            explain: false,
+            float_format: FloatFormat::Shortest,
+            strict: false,
+            clamp_preactivation: None,
+            loss_function: LossFunction::default(),
+            loss_epsilon: 0.0,
+        };
+        let input = "0.5\n-0.2\n1.0\n0.3\nnot-a-number\n0.7\n";
+        let mut nn: NeuralNetwork = config.parse().unwrap();
+        let mut sequential = vec![];
+        assert!(propagate_lines(&mut nn, input.as_bytes(), &mut sequential, &options).is_err());
+        for batch_size in [1, 3, 6] {
+            let mut batched = vec![];
+            assert!(propagate_batches(&nn, input.as_bytes(), &mut batched, &options, batch_size).is_err());
+            assert_eq!(String::from_utf8(batched).unwrap(), String::from_utf8(sequential.clone()).unwrap());
+        }
+        assert_eq!(String::from_utf8(sequential).unwrap().lines().count(), 4);
+    }
+}
@@ -1,195 +1,2197 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cell::{Ref, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
 use std::fs::File;
-use std::io::BufReader;
-use std::path::Path;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+pub mod builder;
+#[cfg(feature = "complex")]
+pub mod complex;
+pub mod gradient_check;
+pub mod gradients;
+pub mod histogram;
+pub mod inference;
+pub mod initializer;
 pub mod loss_function;
+pub mod npz;
+pub mod onnx;
+pub mod statistics;
 
 use crate::error::NeuralError;
-use crate::neurons::{ActivationFunction, Neuron, NeuronType};
-use loss_function::LossFunction;
+use crate::float::Float;
+use crate::neurons::{ActivationFunction, Aggregation, Neuron, NeuronType, Optimizer, RPropState};
+use flate2::read::GzDecoder;
+use gradients::Gradients;
+use inference::InferenceModel;
+use initializer::Initializer;
+use loss_function::{LossFunction, DEFAULT_CROSS_ENTROPY_EPSILON};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 fn default_neuron_activation() -> String {
     "Linear".to_string()
 }
 
-fn default_neuron_bias() -> f64 {
-    0_f64
+fn default_neuron_bias() -> Float {
+    0.0
 }
 
-fn default_empty_synapses() -> HashMap<String, f64> {
+fn default_empty_synapses() -> HashMap<String, SynapseDefs> {
     HashMap::new()
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum SynapseDefs {
+    Weight(Option<Float>),
+    Detailed(DetailedSynapseDefs),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct DetailedSynapseDefs {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    weight: Option<Float>,
+    // identity shortcut with its weight fixed at 1.0
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    residual: bool,
+}
+
+impl SynapseDefs {
+    fn weight(&self) -> Option<Float> {
+        match self {
+            Self::Weight(weight) => *weight,
+            Self::Detailed(defs) if defs.residual => Some(1.0),
+            Self::Detailed(defs) => defs.weight,
+        }
+    }
+
+    fn is_residual(&self) -> bool {
+        matches!(self, Self::Detailed(defs) if defs.residual)
+    }
+
+    fn set_weight(&mut self, weight: Float) {
+        match self {
+            Self::Weight(old_weight) => *old_weight = Some(weight),
+            Self::Detailed(defs) => defs.weight = Some(weight),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct NeuronDefs {
     #[serde(default = "default_neuron_activation")]
     activation: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    activation_params: Vec<Float>,
     #[serde(default = "default_neuron_bias")]
-    bias: f64,
+    bias: Float,
+    // sum or product of the weighted inputs
+    #[serde(default, skip_serializing_if = "Aggregation::is_sum")]
+    aggregation: Aggregation,
+    // a null weight marks a synapse that still has to be initialized
     #[serde(default = "default_empty_synapses")]
-    synapses: HashMap<String, f64>,
+    synapses: HashMap<String, SynapseDefs>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    layer: Option<String>,
+    // input whose value multiplies the activation, 0 switching the neuron off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gate: Option<String>,
+    // written on save so reloading can skip the depth calculation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    depth: Option<u32>,
+    // written after training with RProp so reloading carries on with the same steps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rprop: Option<RPropDefs>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct ConfigJson {
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct RPropDefs {
+    bias: RPropState,
+    // keyed by source id like the synapses
+    synapses: HashMap<String, RPropState>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "aggregation", rename_all = "lowercase")]
+enum AggregateOutputDefs {
+    Sum { neurons: Vec<String> },
+    Mean { neurons: Vec<String> },
+    Max { neurons: Vec<String> },
+    Weighted { weights: HashMap<String, Float> },
+}
+
+impl AggregateOutputDefs {
+    // (source, fixed weight) of every synapse
+    fn synapses(&self) -> Vec<(String, Float)> {
+        match self {
+            Self::Sum { neurons } | Self::Max { neurons } => neurons.iter().map(|id| (id.clone(), 1.0)).collect(),
+            Self::Mean { neurons } => {
+                let weight = 1.0 / neurons.len() as Float;
+                neurons.iter().map(|id| (id.clone(), weight)).collect()
+            }
+            Self::Weighted { weights } => weights.iter().map(|(id, &weight)| (id.clone(), weight)).collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct OutputNormalizationDefs {
+    #[serde(default)]
+    mean: Float,
+    #[serde(default = "default_std")]
+    std: Float,
+}
+
+fn default_std() -> Float {
+    1.0
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct LossTermDefs {
+    // squared, half-squared, absolute or cross-entropy
+    function: String,
+    #[serde(default = "default_loss_weight")]
+    weight: Float,
+    // every output when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    outputs: Vec<String>,
+}
+
+fn default_loss_weight() -> Float {
+    1.0
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct InputBoundsDefs {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min: Option<Float>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max: Option<Float>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct InputGroupDefs {
+    name: String,
     inputs: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum InputDefs {
+    Flat(Vec<String>),
+    Grouped(Vec<InputGroupDefs>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ConfigJson {
+    // configs whose neurons are merged into this one, relative to this config's directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    include: Vec<String>,
+    inputs: InputDefs,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    input_indices: Option<Vec<usize>>,
+    // one-hot encoded inputs, each name takes a single column holding the index of the hot input
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    categorical_inputs: HashMap<String, Vec<String>>,
+    // named groups of neurons whose activations are normalized with a softmax
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    softmax_groups: HashMap<String, Vec<String>>,
+    // expected range of the values of some inputs, checked on every propagation
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    input_bounds: HashMap<String, InputBoundsDefs>,
     outputs: Vec<String>,
+    // outputs computed from other neurons, each must also be listed under outputs
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    aggregate_outputs: HashMap<String, AggregateOutputDefs>,
+    // the network learns (target - mean) / std and its outputs are mapped back to value * std + mean
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    output_normalization: HashMap<String, OutputNormalizationDefs>,
+    // weighted sum of losses over some of the outputs, replacing the loss chosen with --loss
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    loss: Vec<LossTermDefs>,
     neurons: HashMap<String, NeuronDefs>,
 }
 
+// a column of a stdin line, holding either one input's value or the category of a one-hot input
+#[derive(Debug)]
+enum InputColumn {
+    Value(usize),
+    Categorical(String, Vec<usize>),
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigSnapshot(ConfigJson);
+
+// One weighted term of a composite loss
+#[derive(Debug)]
+struct LossTerm {
+    function: LossFunction,
+    weight: Float,
+    // positions in outputs
+    outputs: Vec<usize>,
+}
+
+// Neurons whose activations are replaced by their softmax as soon as the deepest of them has been
+// propagated, so every deeper neuron sees the normalized values.
+#[derive(Debug)]
+struct SoftmaxGroup {
+    name: String,
+    neurons: Vec<Rc<RefCell<Neuron>>>,
+    // sorted index of the last neuron as deep as the deepest member
+    last_index: usize,
+}
+
+impl SoftmaxGroup {
+    fn normalize(&self) {
+        let values: Vec<Float> = self.neurons.iter().map(|neuron| neuron.borrow().get_activation_value()).collect();
+        // shifted by the maximum so exp can't overflow
+        let max = values.iter().copied().fold(Float::NEG_INFINITY, Float::max);
+        let exps: Vec<Float> = values.iter().map(|value| (value - max).exp()).collect();
+        let sum: Float = exps.iter().sum();
+        for (neuron, exp) in self.neurons.iter().zip(exps) {
+            neuron.borrow_mut().set_normalized_activation_value(exp / sum);
+        }
+    }
+
+    // turns the errors of the softmax values into errors of the activations they were computed
+    // from: e_i = s_i * (e_i - sum_j e_j * s_j)
+    fn backpropagate_error(&self, errors: &mut [Float]) {
+        let values: Vec<(usize, Float)> = self
+            .neurons
+            .iter()
+            .map(|neuron| {
+                let neuron = neuron.borrow();
+                (neuron.get_index(), neuron.get_activation_value())
+            })
+            .collect();
+        let weighted: Float = values.iter().map(|&(index, value)| errors[index] * value).sum();
+        for (index, value) in values {
+            errors[index] = value * (errors[index] - weighted);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LoadOptions {
+    pub max_depth: Option<u32>,
+    pub max_fan_in: Option<usize>,
+    // reject activation names not spelled exactly as they are saved, e.g. 'relu' for 'ReLU'
+    pub strict_activation_names: bool,
+    // recalculate the depths even when the config has them and fail if they differ
+    pub verify_depths: bool,
+    // turn warnings about likely mistakes into errors
+    pub strict: bool,
+    // bound every pre-activation into [-max, max] before the activation is applied
+    pub clamp_preactivation: Option<Float>,
+}
+
 #[derive(Debug)]
 pub struct NeuralNetwork {
     inputs: Vec<Rc<RefCell<Neuron>>>,
+    // name and size of each input group, in the order they appear on a stdin line
+    input_groups: Option<Vec<(String, usize)>>,
+    input_indices: Option<Vec<usize>>,
+    // column layout of a stdin line when it has categorical inputs, positions index into inputs
+    input_columns: Option<Vec<InputColumn>>,
+    // (min, max) of every input in input order, None when no input has bounds
+    input_bounds: Option<Vec<(Float, Float)>>,
+    // out of bounds inputs fail the propagation instead of logging a warning
+    strict_input_bounds: bool,
     outputs: Vec<Rc<RefCell<Neuron>>>,
+    // kept to save them as they were given, the neurons computing them are in neuron_map
+    aggregate_outputs: HashMap<String, AggregateOutputDefs>,
+    // (mean, std) of every output in output order, None when no output is normalized
+    output_normalization: Option<Vec<(Float, Float)>>,
     neuron_map: HashMap<String, Rc<RefCell<Neuron>>>,
     sorted_neurons: Vec<Rc<RefCell<Neuron>>>,
+    layers: Vec<(String, Vec<Rc<RefCell<Neuron>>>)>,
+    softmax_groups: Vec<SoftmaxGroup>,
     loss_function: LossFunction,
+    // composite loss of the config, empty applies loss_function to every output
+    loss_terms: Vec<LossTerm>,
+    // multiplies the loss derivative seeding the backpropagation, not the reported loss
+    loss_scale: Float,
+    optimizer: Optimizer,
+    // training only updates the weights, e.g. when fine-tuning
+    freeze_biases: bool,
+    // training targets become (1 - alpha) * target + alpha / labeled outputs
+    label_smoothing: Float,
+    // bound of the pre-activations, None leaves them as they are
+    clamp_preactivation: Option<Float>,
+    // error of every neuron from the last backpropagation, indexed by neuron index
+    error_map: Vec<Float>,
 }
 
 impl NeuralNetwork {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, NeuralError> {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        let cfg: ConfigJson = serde_json::from_reader(reader)
-            .map_err(|e| NeuralError::ParseError(e.to_string()))?;
-        
-        let mut nn = NeuralNetwork {
+        Self::new_with_options(path, &LoadOptions::default())
+    }
+
+    pub fn new_with_options<P: AsRef<Path>>(path: P, options: &LoadOptions) -> Result<Self, NeuralError> {
+        Self::from_config(Self::read_config(path)?, options)
+    }
+
+    // reads a config without touching the filesystem, so it can't include other files
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, NeuralError> {
+        Self::from_reader_with_options(reader, &LoadOptions::default())
+    }
+
+    pub fn from_reader_with_options<R: Read>(reader: R, options: &LoadOptions) -> Result<Self, NeuralError> {
+        let cfg = Self::parse_config(reader)?;
+        if let Some(include) = cfg.include.first() {
+            return Err(NeuralError::NetworkError(format!(
+                "Can't include '{}' in a config that wasn't read from a file",
+                include
+            )));
+        }
+        Self::from_config(cfg, options)
+    }
+
+    // e.g. a config embedded with include_bytes!, which may be gzip compressed
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, NeuralError> {
+        Self::from_reader(bytes)
+    }
+
+    pub fn new_initialized<P: AsRef<Path>, R: Rng + ?Sized>(
+        path: P,
+        initializer: Option<&Initializer>,
+        init_biases: bool,
+        rng: &mut R,
+    ) -> Result<Self, NeuralError> {
+        Self::initialize_config(Self::read_config(path)?, initializer, init_biases, rng)
+    }
+
+    pub fn new_layered_initialized<R: Rng + ?Sized>(
+        layer_sizes: &[usize],
+        activation: &str,
+        initializer: Option<&Initializer>,
+        init_biases: bool,
+        rng: &mut R,
+    ) -> Result<Self, NeuralError> {
+        Self::initialize_config(Self::layered_config(layer_sizes, activation)?, initializer, init_biases, rng)
+    }
+
+    // fully connected layers: i0.. as inputs, h1_0.. as hidden neurons and o0.. as linear outputs
+    fn layered_config(layer_sizes: &[usize], activation: &str) -> Result<ConfigJson, NeuralError> {
+        ActivationFunction::with_params(activation, &[])?;
+        if layer_sizes.len() < 2 || layer_sizes.contains(&0) {
+            return Err(NeuralError::ParseError(format!(
+                "Expected at least an input and an output layer size, all non zero, got {:?}",
+                layer_sizes
+            )));
+        }
+        let last_layer = layer_sizes.len() - 1;
+        let layer_names: Vec<Vec<String>> = layer_sizes
+            .iter()
+            .enumerate()
+            .map(|(layer, &size)| {
+                (0..size)
+                    .map(|i| match layer {
+                        0 => format!("i{}", i),
+                        _ if layer == last_layer => format!("o{}", i),
+                        _ => format!("h{}_{}", layer, i),
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut neurons = HashMap::new();
+        for (layer, names) in layer_names.iter().enumerate().skip(1) {
+            for name in names {
+                let neuron_defs = NeuronDefs {
+                    activation: if layer == last_layer {
+                        default_neuron_activation()
+                    } else {
+                        activation.to_string()
+                    },
+                    activation_params: vec![],
+                    bias: default_neuron_bias(),
+                    aggregation: Aggregation::Sum,
+                    synapses: layer_names[layer - 1].iter().map(|lname| (lname.clone(), SynapseDefs::Weight(None))).collect(),
+                    layer: None,
+                    gate: None,
+                    depth: None,
+                    rprop: None,
+                };
+                neurons.insert(name.clone(), neuron_defs);
+            }
+        }
+        Ok(ConfigJson {
+            include: vec![],
+            inputs: InputDefs::Flat(layer_names[0].clone()),
+            input_indices: None,
+            categorical_inputs: HashMap::new(),
+            softmax_groups: HashMap::new(),
+            input_bounds: HashMap::new(),
+            outputs: layer_names[last_layer].clone(),
+            aggregate_outputs: HashMap::new(),
+            output_normalization: HashMap::new(),
+            loss: vec![],
+            neurons,
+        })
+    }
+
+    fn initialize_config<R: Rng + ?Sized>(
+        mut cfg: ConfigJson,
+        initializer: Option<&Initializer>,
+        init_biases: bool,
+        rng: &mut R,
+    ) -> Result<Self, NeuralError> {
+        let mut fan_outs: HashMap<String, usize> = HashMap::new();
+        for neuron_defs in cfg.neurons.values() {
+            for lneuron_name in neuron_defs.synapses.keys() {
+                *fan_outs.entry(lneuron_name.clone()).or_insert(0) += 1;
+            }
+        }
+        // sorted so that a seeded rng always produces the same network
+        let mut neuron_names: Vec<String> = cfg.neurons.keys().cloned().collect();
+        neuron_names.sort();
+        for neuron_name in neuron_names {
+            let neuron_defs = cfg.neurons.get_mut(&neuron_name).unwrap();
+            // without an initializer one is picked from the activation of each neuron
+            let auto_initializer;
+            let initializer = match initializer {
+                Some(initializer) => initializer,
+                None => {
+                    let activation =
+                        ActivationFunction::with_params(&neuron_defs.activation, &neuron_defs.activation_params)?;
+                    let fan_out = fan_outs.get(&neuron_name).copied().unwrap_or(0);
+                    auto_initializer =
+                        Initializer::for_activation(&activation, neuron_defs.synapses.len(), fan_out)?;
+                    &auto_initializer
+                }
+            };
+            if init_biases {
+                neuron_defs.bias = initializer.sample(rng);
+            }
+            let mut synapse_names: Vec<String> = neuron_defs.synapses.keys().cloned().collect();
+            synapse_names.sort();
+            for synapse_name in synapse_names {
+                let synapse = neuron_defs.synapses.get_mut(&synapse_name).unwrap();
+                if synapse.weight().is_none() {
+                    synapse.set_weight(initializer.sample(rng));
+                }
+            }
+        }
+        Self::from_config(cfg, &LoadOptions::default())
+    }
+
+    // plain or gzip compressed JSON, gzip is recognized by its magic bytes so the extension doesn't matter
+    fn parse_config<R: Read>(reader: R) -> Result<ConfigJson, NeuralError> {
+        let mut reader = BufReader::new(reader);
+        let is_gzip = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+        let reader: Box<dyn Read> = match is_gzip {
+            true => Box::new(BufReader::new(GzDecoder::new(reader))),
+            false => Box::new(reader),
+        };
+        serde_json::from_reader(reader).map_err(|e| NeuralError::ParseError(e.to_string()))
+    }
+
+    fn read_config<P: AsRef<Path>>(path: P) -> Result<ConfigJson, NeuralError> {
+        Self::read_config_including(path.as_ref(), &mut vec![])
+    }
+
+    fn read_config_including(path: &Path, include_stack: &mut Vec<PathBuf>) -> Result<ConfigJson, NeuralError> {
+        // pipes like bash process substitutions can't be canonicalized, but can't be included either
+        let canonical_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if let Some(start) = include_stack.iter().position(|included| *included == canonical_path) {
+            let cycle: Vec<String> = include_stack[start..]
+                .iter()
+                .chain([&canonical_path])
+                .map(|included| included.display().to_string())
+                .collect();
+            return Err(NeuralError::NetworkError(format!("Include cycle: {}", cycle.join(" -> "))));
+        }
+        let context = || format!("while reading '{}'", path.display());
+        let file = File::open(path).map_err(|e| NeuralError::from(e).context(context()))?;
+        let mut cfg = Self::parse_config(file).map_err(|e| e.context(context()))?;
+        include_stack.push(canonical_path);
+        for include in std::mem::take(&mut cfg.include) {
+            let include_path = path.parent().unwrap_or(Path::new("")).join(&include);
+            let included = Self::read_config_including(&include_path, include_stack)
+                .map_err(|e| e.context(format!("while including '{}'", include_path.display())))?;
+            // 'block.json.gz' is included as 'block' too
+            let stem_path = match include_path.extension() {
+                Some(extension) if extension == "gz" => include_path.with_extension(""),
+                _ => include_path.clone(),
+            };
+            let prefix = stem_path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or(include);
+            Self::merge_included(&mut cfg, included, &prefix)?;
+        }
+        include_stack.pop();
+        Ok(cfg)
+    }
+
+    // Adds the included neurons as '<prefix>.<id>'. Synapses between included neurons are renamed
+    // along, every other synapse (e.g. from the included config's inputs) keeps its name and so
+    // connects to the including network's neuron of that name.
+    fn merge_included(cfg: &mut ConfigJson, included: ConfigJson, prefix: &str) -> Result<(), NeuralError> {
+        let included_ids: HashSet<String> = included.neurons.keys().cloned().collect();
+        // these sections describe the included file's own inputs and outputs, which aren't merged,
+        // so they would be dropped without notice
+        let unsupported = [
+            ("input_indices", included.input_indices.is_some()),
+            ("categorical_inputs", !included.categorical_inputs.is_empty()),
+            ("input_bounds", !included.input_bounds.is_empty()),
+            ("aggregate_outputs", !included.aggregate_outputs.is_empty()),
+            ("output_normalization", !included.output_normalization.is_empty()),
+            ("loss", !included.loss.is_empty()),
+        ];
+        if let Some((section, _)) = unsupported.iter().find(|(_, present)| *present) {
+            return Err(NeuralError::NetworkError(format!(
+                "Include '{}' has '{}', only its neurons and softmax_groups can be included",
+                prefix, section
+            )));
+        }
+        if let Some(output) = included.outputs.iter().find(|id| !included_ids.contains(*id)) {
+            return Err(NeuralError::NetworkError(format!(
+                "Output '{}' of include '{}' is not one of its neurons",
+                output, prefix
+            )));
+        }
+        let input_ids: HashSet<&String> = match &cfg.inputs {
+            InputDefs::Flat(names) => names.iter().collect(),
+            InputDefs::Grouped(groups) => groups.iter().flat_map(|group| group.inputs.iter()).collect(),
+        };
+        for (id, mut neuron_defs) in included.neurons {
+            let id = format!("{}.{}", prefix, id);
+            if cfg.neurons.contains_key(&id) || input_ids.contains(&id) {
+                return Err(NeuralError::NetworkError(format!(
+                    "Neuron id '{}' of include '{}' is already taken",
+                    id, prefix
+                )));
+            }
+            neuron_defs.synapses = neuron_defs
+                .synapses
+                .into_iter()
+                .map(|(lneuron_id, synapse)| match included_ids.contains(&lneuron_id) {
+                    true => (format!("{}.{}", prefix, lneuron_id), synapse),
+                    false => (lneuron_id, synapse),
+                })
+                .collect();
+            neuron_defs.layer = neuron_defs.layer.map(|layer| format!("{}.{}", prefix, layer));
+            neuron_defs.gate = neuron_defs.gate.map(|gate| match included_ids.contains(&gate) {
+                true => format!("{}.{}", prefix, gate),
+                false => gate,
+            });
+            // the synapses to the including config can change the depths
+            neuron_defs.depth = None;
+            cfg.neurons.insert(id, neuron_defs);
+        }
+        for (name, ids) in included.softmax_groups {
+            let ids = ids.into_iter().map(|id| format!("{}.{}", prefix, id)).collect();
+            cfg.softmax_groups.insert(format!("{}.{}", prefix, name), ids);
+        }
+        Ok(())
+    }
+
+    fn empty() -> Self {
+        NeuralNetwork {
             inputs: vec![],
+            input_groups: None,
+            input_indices: None,
+            input_columns: None,
+            input_bounds: None,
+            strict_input_bounds: false,
             outputs: vec![],
+            aggregate_outputs: HashMap::new(),
+            output_normalization: None,
             neuron_map: HashMap::new(),
             sorted_neurons: vec![],
+            layers: vec![],
+            softmax_groups: vec![],
             loss_function: LossFunction::new(),
-        };
+            loss_terms: vec![],
+            loss_scale: 1.0,
+            optimizer: Optimizer::default(),
+            freeze_biases: false,
+            label_smoothing: 0.0,
+            clamp_preactivation: None,
+            error_map: vec![],
+        }
+    }
 
+    fn from_config(cfg: ConfigJson, options: &LoadOptions) -> Result<Self, NeuralError> {
+        let mut nn = Self::empty();
         nn.create_inputs(&cfg.inputs);
+        nn.set_input_indices(cfg.input_indices)?;
+        nn.set_categorical_inputs(&cfg.categorical_inputs)?;
+        nn.set_input_bounds(&cfg.input_bounds)?;
+        nn.set_strict_input_bounds(options.strict);
+        if let Some(max) = options.clamp_preactivation.filter(|max| max.is_nan() || *max <= 0.0) {
+            return Err(NeuralError::NetworkError(format!("The pre-activation clamp must be positive, got {}", max)));
+        }
+        nn.set_clamp_preactivation(options.clamp_preactivation);
         for (neuron_name, neuron_defs) in &cfg.neurons {
-            let activation = ActivationFunction::new(neuron_defs.activation.as_str());
+            let activation =
+                ActivationFunction::with_params(neuron_defs.activation.as_str(), &neuron_defs.activation_params)
+                    .map_err(|e| e.context(format!("while creating neuron '{}'", neuron_name)))?;
+            if options.strict_activation_names && activation.get_name() != neuron_defs.activation {
+                return Err(NeuralError::ParseError(format!(
+                    "Activation '{}' of neuron '{}' is not spelled canonically, expected '{}'",
+                    neuron_defs.activation,
+                    neuron_name,
+                    activation.get_name()
+                )));
+            }
+            if activation.is_maxout() && neuron_defs.aggregation == Aggregation::Product {
+                return Err(NeuralError::NetworkError(format!(
+                    "Neuron '{}' can't be a Maxout neuron with product aggregation",
+                    neuron_name
+                )));
+            }
             nn.create_neuron(neuron_name, activation, neuron_defs.bias)?;
+            nn.neuron_map[neuron_name].borrow_mut().set_aggregation(neuron_defs.aggregation);
         }
         for (rneuron_name, neuron_defs) in &cfg.neurons {
-            for (lneuron_name, &weight) in &neuron_defs.synapses {
-                nn.connect_neurons(lneuron_name.as_str(), rneuron_name.as_str(), weight)?;
+            for (lneuron_name, synapse) in &neuron_defs.synapses {
+                if synapse.is_residual() {
+                    if let SynapseDefs::Detailed(DetailedSynapseDefs { weight: Some(weight), .. }) = synapse {
+                        if *weight != 1.0 {
+                            return Err(NeuralError::NetworkError(format!(
+                                "Residual synapse '{}' of neuron '{}' can't have a weight other than 1.0, got {}",
+                                lneuron_name, rneuron_name, weight
+                            )));
+                        }
+                    }
+                    nn.connect_residual(lneuron_name.as_str(), rneuron_name.as_str()).map_err(|e| {
+                        e.context(format!("while connecting '{}' -> '{}'", lneuron_name, rneuron_name))
+                    })?;
+                    continue;
+                }
+                let weight = synapse.weight().ok_or_else(|| NeuralError::NetworkError(format!(
+                    "Synapse '{}' of neuron '{}' has no weight, initialize it with 'mmnn init'",
+                    lneuron_name, rneuron_name
+                )))?;
+                nn.connect_neurons(lneuron_name.as_str(), rneuron_name.as_str(), weight).map_err(|e| {
+                    e.context(format!("while connecting '{}' -> '{}'", lneuron_name, rneuron_name))
+                })?;
+            }
+        }
+        nn.create_aggregate_outputs(&cfg.aggregate_outputs, &cfg.outputs)?;
+        for (neuron_name, neuron_defs) in &cfg.neurons {
+            if let Some(gate) = &neuron_defs.gate {
+                nn.set_gate(gate, neuron_name)?;
+            }
+        }
+        nn.restore_rprop_states(&cfg.neurons)?;
+        nn.create_layers(&cfg.neurons)?;
+        nn.create_softmax_groups(&cfg.softmax_groups)?;
+        let cached_depths = Self::cached_depths(&cfg.neurons);
+        if let Some(depths) = cached_depths.as_ref().filter(|_| !options.verify_depths) {
+            match nn.check_cached_depths(depths) {
+                Ok(()) => nn.set_depths(depths),
+                Err(e) => log::debug!("Recalculating the depths, {}", e),
+            }
+        }
+        nn.finish(&cfg.outputs, options)?;
+        nn.set_output_normalization(&cfg.output_normalization)?;
+        nn.set_loss_terms(&cfg.loss)?;
+        if let Some(depths) = cached_depths.filter(|_| options.verify_depths) {
+            nn.verify_depths(&depths)?;
+        }
+        Ok(nn)
+    }
+
+    fn restore_rprop_states(&mut self, neuron_defs: &HashMap<String, NeuronDefs>) -> Result<(), NeuralError> {
+        for (id, defs) in neuron_defs {
+            let Some(rprop) = &defs.rprop else {
+                continue;
+            };
+            let mut neuron = self.neuron_map[id].borrow_mut();
+            let source_ids = neuron.get_synapse_ids();
+            if source_ids.len() != rprop.synapses.len() {
+                return Err(NeuralError::NetworkError(format!(
+                    "Neuron '{}' has {} synapses but RProp state for {}",
+                    id,
+                    source_ids.len(),
+                    rprop.synapses.len()
+                )));
+            }
+            let mut states = vec![rprop.bias.clone()];
+            for source_id in source_ids {
+                let state = rprop.synapses.get(&source_id).ok_or_else(|| {
+                    NeuralError::NetworkError(format!("Neuron '{}' has no RProp state for the synapse from '{}'", id, source_id))
+                })?;
+                states.push(state.clone());
+            }
+            neuron.set_rprop_states(states);
+        }
+        Ok(())
+    }
+
+    // only used when every neuron has one
+    fn cached_depths(neuron_defs: &HashMap<String, NeuronDefs>) -> Option<HashMap<String, u32>> {
+        neuron_defs
+            .iter()
+            .map(|(id, defs)| defs.depth.map(|depth| (id.clone(), depth)))
+            .collect()
+    }
+
+    // every neuron must be one deeper than its deepest shallower source, synapses from deeper
+    // sources are the recurrent ones and have to close a cycle, otherwise a stale depth would turn
+    // a feed-forward synapse into a recurrent one. Only the neuron itself may be as deep as one of
+    // its sources: propagation would read the current value of another neuron at the same depth
+    // while backpropagation treats the synapse as recurrent.
+    fn check_cached_depths(&self, depths: &HashMap<String, u32>) -> Result<(), NeuralError> {
+        let mut components: Option<HashMap<String, usize>> = None;
+        for (id, &depth) in depths.iter() {
+            let neuron = self.neuron_map[id].borrow();
+            let sources = neuron.get_synapses_map();
+            let deepest_source = sources
+                .keys()
+                .map(|source| depths.get(source).copied().unwrap_or(0))
+                .filter(|&source_depth| source_depth < depth)
+                .max();
+            let consistent = match deepest_source {
+                Some(source_depth) => source_depth + 1 == depth,
+                None => sources.is_empty() && depth == 0,
+            };
+            if !consistent {
+                return Err(NeuralError::NetworkError(format!(
+                    "Neuron id '{}': Cached depth {} is inconsistent with its synapses",
+                    id, depth
+                )));
+            }
+            for source in sources.keys() {
+                let source_depth = depths.get(source).copied().unwrap_or(0);
+                if source == id || source_depth < depth {
+                    continue;
+                }
+                if source_depth == depth {
+                    return Err(NeuralError::NetworkError(format!(
+                        "Neuron id '{}': Cached depth {} is also the depth of its source '{}'",
+                        id, depth, source
+                    )));
+                }
+                let components = components.get_or_insert_with(|| self.cycle_components());
+                if components.get(source) != components.get(id) {
+                    return Err(NeuralError::NetworkError(format!(
+                        "Neuron id '{}': Cached depth {} makes the synapse from '{}' recurrent but it is not part of a cycle",
+                        id, depth, source
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Strongly connected component of every neuron, two neurons share one when each can reach the
+    // other, i.e. when they are on a cycle. Tarjan's algorithm without recursion so deep networks
+    // can't overflow the stack.
+    fn cycle_components(&self) -> HashMap<String, usize> {
+        let ids: Vec<&String> = self.neuron_map.keys().collect();
+        let positions: HashMap<&str, usize> = ids.iter().enumerate().map(|(i, id)| (id.as_str(), i)).collect();
+        let sources: Vec<Vec<usize>> = ids
+            .iter()
+            .map(|id| {
+                let neuron = self.neuron_map[*id].borrow();
+                neuron.get_synapse_ids().iter().filter_map(|source| positions.get(source.as_str()).copied()).collect()
+            })
+            .collect();
+        let mut order = vec![usize::MAX; ids.len()];
+        let mut lowlink = vec![0; ids.len()];
+        let mut on_stack = vec![false; ids.len()];
+        let mut component = vec![0; ids.len()];
+        let mut stack = vec![];
+        let mut visited = 0;
+        let mut components = 0;
+        for root in 0..ids.len() {
+            if order[root] != usize::MAX {
+                continue;
+            }
+            // (neuron, next of its sources to visit)
+            let mut calls = vec![(root, 0)];
+            order[root] = visited;
+            lowlink[root] = visited;
+            visited += 1;
+            stack.push(root);
+            on_stack[root] = true;
+            while let Some(call) = calls.last_mut() {
+                let v = call.0;
+                if let Some(&w) = sources[v].get(call.1) {
+                    call.1 += 1;
+                    if order[w] == usize::MAX {
+                        order[w] = visited;
+                        lowlink[w] = visited;
+                        visited += 1;
+                        stack.push(w);
+                        on_stack[w] = true;
+                        calls.push((w, 0));
+                    } else if on_stack[w] {
+                        lowlink[v] = lowlink[v].min(order[w]);
+                    }
+                    continue;
+                }
+                calls.pop();
+                if let Some(&(parent, _)) = calls.last() {
+                    lowlink[parent] = lowlink[parent].min(lowlink[v]);
+                }
+                if lowlink[v] == order[v] {
+                    while let Some(w) = stack.pop() {
+                        on_stack[w] = false;
+                        component[w] = components;
+                        if w == v {
+                            break;
+                        }
+                    }
+                    components += 1;
+                }
+            }
+        }
+        ids.into_iter().cloned().zip(component).collect()
+    }
+
+    fn set_depths(&self, depths: &HashMap<String, u32>) {
+        for input in self.inputs.iter() {
+            input.borrow_mut().set_depth(0);
+        }
+        for (id, &depth) in depths.iter() {
+            self.neuron_map[id].borrow_mut().set_depth(depth);
+        }
+    }
+
+    fn verify_depths(&self, depths: &HashMap<String, u32>) -> Result<(), NeuralError> {
+        for (id, &depth) in depths.iter() {
+            let calculated = self.neuron_map[id].borrow().get_depth();
+            if calculated != depth {
+                return Err(NeuralError::NetworkError(format!(
+                    "Neuron id '{}': Cached depth {} differs from the calculated depth {}",
+                    id, depth, calculated
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, output_names: &[String], options: &LoadOptions) -> Result<(), NeuralError> {
+        self.create_outputs(output_names)?;
+        self.check_disconnected_outputs(options.strict)?;
+        if let Some(max_fan_in) = options.max_fan_in {
+            self.check_fan_in(max_fan_in)?;
+        }
+        self.calculate_depths(options.max_depth)?;
+        self.create_sorted_neuron_list();
+        log::debug!(
+            "Loaded network with {} inputs, {} outputs and {} neurons",
+            self.inputs.len(),
+            self.outputs.len(),
+            self.neuron_map.len()
+        );
+        Ok(())
+    }
+
+    fn check_disconnected_outputs(&self, strict: bool) -> Result<(), NeuralError> {
+        for output in self.outputs.iter() {
+            let output = output.borrow();
+            if output.is_input() || output.get_synapse_count() > 0 {
+                continue;
+            }
+            let message = format!(
+                "Output neuron '{}' has no synapses, it always outputs its bias through its activation",
+                output.get_id()
+            );
+            if strict {
+                return Err(NeuralError::NetworkError(message));
+            }
+            log::warn!("{}", message);
+        }
+        Ok(())
+    }
+
+    fn check_fan_in(&self, max_fan_in: usize) -> Result<(), NeuralError> {
+        for (neuron_id, neuron) in self.neuron_map.iter() {
+            let fan_in = neuron.borrow().get_synapse_count();
+            if fan_in > max_fan_in {
+                return Err(NeuralError::NetworkError(format!(
+                    "Neuron id '{}': {} incoming synapses exceed the maximum fan-in {}",
+                    neuron_id, fan_in, max_fan_in
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn create_inputs(&mut self, input_defs: &InputDefs) {
+        let input_names: Vec<&String> = match input_defs {
+            InputDefs::Flat(names) => names.iter().collect(),
+            InputDefs::Grouped(groups) => {
+                self.input_groups = Some(
+                    groups
+                        .iter()
+                        .map(|group| (group.name.clone(), group.inputs.len()))
+                        .collect(),
+                );
+                groups.iter().flat_map(|group| group.inputs.iter()).collect()
+            }
+        };
+        for id in input_names {
+            let neuron = Rc::new(RefCell::new(Neuron::new(
+                id,
+                NeuronType::Input,
+                ActivationFunction::Linear,
+                0.0,
+            )));
+            self.neuron_map.insert(id.to_owned(), Rc::clone(&neuron));
+            self.inputs.push(neuron);
+        }
+    }
+
+    fn set_input_indices(&mut self, input_indices: Option<Vec<usize>>) -> Result<(), NeuralError> {
+        if let Some(indices) = &input_indices {
+            if self.input_groups.is_some() {
+                return Err(NeuralError::NetworkError(
+                    "Input indices can't be combined with input groups".to_string(),
+                ));
+            }
+            if indices.len() != self.inputs.len() {
+                return Err(NeuralError::NetworkError(format!(
+                    "Input indices do not match the inputs. {} vs {}",
+                    indices.len(),
+                    self.inputs.len()
+                )));
+            }
+        }
+        self.input_indices = input_indices;
+        Ok(())
+    }
+
+    fn set_input_bounds(&mut self, input_bounds: &HashMap<String, InputBoundsDefs>) -> Result<(), NeuralError> {
+        if input_bounds.is_empty() {
+            return Ok(());
+        }
+        let mut bounds = vec![(Float::NEG_INFINITY, Float::INFINITY); self.inputs.len()];
+        for (id, defs) in input_bounds {
+            let position = self
+                .inputs
+                .iter()
+                .position(|neuron| neuron.borrow().get_id() == id)
+                .ok_or_else(|| NeuralError::NetworkError(format!("Bounds given for '{}' which is not an input", id)))?;
+            let min = defs.min.unwrap_or(Float::NEG_INFINITY);
+            let max = defs.max.unwrap_or(Float::INFINITY);
+            if min > max {
+                return Err(NeuralError::NetworkError(format!(
+                    "Input '{}' has a min {} greater than its max {}",
+                    id, min, max
+                )));
+            }
+            bounds[position] = (min, max);
+        }
+        self.input_bounds = Some(bounds);
+        Ok(())
+    }
+
+    fn set_output_normalization(
+        &mut self,
+        output_normalization: &HashMap<String, OutputNormalizationDefs>,
+    ) -> Result<(), NeuralError> {
+        if output_normalization.is_empty() {
+            return Ok(());
+        }
+        let mut normalization = vec![(0.0, 1.0); self.outputs.len()];
+        for (id, defs) in output_normalization {
+            let position = self
+                .outputs
+                .iter()
+                .position(|neuron| neuron.borrow().get_id() == id)
+                .ok_or_else(|| NeuralError::NetworkError(format!("Normalization given for '{}' which is not an output", id)))?;
+            if !defs.mean.is_finite() || !defs.std.is_finite() || defs.std <= 0.0 {
+                return Err(NeuralError::NetworkError(format!(
+                    "Output '{}' needs a finite mean and a positive std, got {} and {}",
+                    id, defs.mean, defs.std
+                )));
+            }
+            normalization[position] = (defs.mean, defs.std);
+        }
+        self.output_normalization = Some(normalization);
+        Ok(())
+    }
+
+    // std of the output at this position, 1.0 without normalization
+    fn output_std(&self, position: usize) -> Float {
+        self.output_normalization.as_ref().map_or(1.0, |normalization| normalization[position].1)
+    }
+
+    // maps expected outputs into the space the network is trained in, NaN stays NaN
+    fn normalize_expected<'a>(&self, expected_output_values: &'a [Float]) -> Cow<'a, [Float]> {
+        let Some(normalization) = &self.output_normalization else {
+            return Cow::Borrowed(expected_output_values);
+        };
+        expected_output_values
+            .iter()
+            .zip(normalization.iter())
+            .map(|(value, (mean, std))| (value - mean) / std)
+            .collect()
+    }
+
+    fn set_loss_terms(&mut self, terms: &[LossTermDefs]) -> Result<(), NeuralError> {
+        self.loss_terms = vec![];
+        for defs in terms {
+            let function = LossFunction::from_name(&defs.function, DEFAULT_CROSS_ENTROPY_EPSILON).ok_or_else(|| {
+                NeuralError::NetworkError(format!(
+                    "Unknown loss '{}', expected squared, half-squared, absolute or cross-entropy",
+                    defs.function
+                ))
+            })?;
+            if !defs.weight.is_finite() || defs.weight <= 0.0 {
+                return Err(NeuralError::NetworkError(format!(
+                    "Loss '{}' needs a finite positive weight, got {}",
+                    defs.function, defs.weight
+                )));
+            }
+            let outputs = match defs.outputs.is_empty() {
+                true => (0..self.outputs.len()).collect(),
+                false => defs
+                    .outputs
+                    .iter()
+                    .map(|id| {
+                        self.outputs
+                            .iter()
+                            .position(|neuron| neuron.borrow().get_id() == id)
+                            .ok_or_else(|| NeuralError::NetworkError(format!("Loss given for '{}' which is not an output", id)))
+                    })
+                    .collect::<Result<_, _>>()?,
+            };
+            self.loss_terms.push(LossTerm {
+                function,
+                weight: defs.weight,
+                outputs,
+            });
+        }
+        Ok(())
+    }
+
+    fn loss_terms(&self) -> Vec<LossTermDefs> {
+        self.loss_terms
+            .iter()
+            .map(|term| LossTermDefs {
+                function: term.function.get_name().to_string(),
+                weight: term.weight,
+                outputs: term
+                    .outputs
+                    .iter()
+                    .map(|&position| self.outputs[position].borrow().get_id().to_string())
+                    .collect(),
+            })
+            .collect()
+    }
+
+    // loss of the current outputs, expected values already normalized
+    fn loss(&self, expected_output_values: &[Float]) -> Float {
+        let output_results: Vec<Float> = self
+            .outputs
+            .iter()
+            .map(|x| x.borrow().get_activation_value())
+            .collect();
+        if self.loss_terms.is_empty() {
+            return self.loss_function.get_error(&output_results, expected_output_values);
+        }
+        let mut total = 0.0;
+        for term in self.loss_terms.iter() {
+            for &position in term.outputs.iter().filter(|&&position| !expected_output_values[position].is_nan()) {
+                total += term.weight * term.function.get_loss(output_results[position], expected_output_values[position]);
+            }
+        }
+        total
+    }
+
+    // derivative of the loss with respect to every output, 0 for unlabeled ones, summed over the
+    // terms an output is part of
+    fn loss_derivatives(&self, expected_output_values: &[Float]) -> Vec<Float> {
+        let mut derivatives = vec![0.0; self.outputs.len()];
+        for (position, (neuron, expected)) in self.outputs.iter().zip(expected_output_values).enumerate() {
+            // masked outputs keep a zero error so no gradient flows from them
+            if expected.is_nan() {
+                continue;
+            }
+            let value = neuron.borrow().get_activation_value();
+            derivatives[position] = match self.loss_terms.is_empty() {
+                true => self.loss_function.get_derivative(value, *expected),
+                false => self
+                    .loss_terms
+                    .iter()
+                    .filter(|term| term.outputs.contains(&position))
+                    .map(|term| term.weight * term.function.get_derivative(value, *expected))
+                    .sum(),
+            };
+        }
+        derivatives
+    }
+
+    pub fn set_loss_function(&mut self, loss_function: LossFunction) {
+        self.loss_function = loss_function;
+    }
+
+    // the clamp of the cross-entropy terms of the config, the loss set above carries its own
+    pub fn set_loss_epsilon(&mut self, epsilon: Float) {
+        for term in self.loss_terms.iter_mut() {
+            if let LossFunction::LossCrossEntropy(_) = term.function {
+                term.function = LossFunction::LossCrossEntropy(epsilon);
+            }
+        }
+    }
+
+    pub fn set_loss_scale(&mut self, loss_scale: Float) {
+        self.loss_scale = loss_scale;
+    }
+
+    pub fn set_optimizer(&mut self, optimizer: Optimizer) {
+        self.optimizer = optimizer;
+    }
+
+    pub fn set_freeze_biases(&mut self, freeze_biases: bool) {
+        self.freeze_biases = freeze_biases;
+    }
+
+    // drops the optimizer state loaded with the config, the next update starts it over
+    pub fn clear_optimizer_state(&mut self) {
+        for neuron in self.sorted_neurons.iter() {
+            neuron.borrow_mut().set_rprop_states(vec![]);
+        }
+    }
+
+    pub fn set_label_smoothing(&mut self, alpha: Float) {
+        self.label_smoothing = alpha;
+    }
+
+    // the targets the training steps use, unlabeled outputs stay NaN and don't count
+    fn smooth_labels<'a>(&self, expected_output_values: &'a [Float]) -> Cow<'a, [Float]> {
+        if self.label_smoothing == 0.0 {
+            return Cow::Borrowed(expected_output_values);
+        }
+        let labeled = expected_output_values.iter().filter(|value| !value.is_nan()).count();
+        let alpha = self.label_smoothing;
+        expected_output_values
+            .iter()
+            .map(|value| (1.0 - alpha) * value + alpha / labeled as Float)
+            .collect()
+    }
+
+    // max has to be positive
+    pub fn set_clamp_preactivation(&mut self, max: Option<Float>) {
+        self.clamp_preactivation = max;
+    }
+
+    pub fn set_strict_input_bounds(&mut self, strict: bool) {
+        self.strict_input_bounds = strict;
+    }
+
+    fn check_input_bounds(&self, input_values: &[Float]) -> Result<(), String> {
+        let Some(bounds) = &self.input_bounds else {
+            return Ok(());
+        };
+        for ((value, neuron), (min, max)) in input_values.iter().zip(self.inputs.iter()).zip(bounds.iter()) {
+            if (*min..=*max).contains(value) {
+                continue;
+            }
+            let message = format!(
+                "Input '{}' value {} is outside of its bounds [{}, {}]",
+                neuron.borrow().get_id(),
+                value,
+                min,
+                max
+            );
+            if self.strict_input_bounds {
+                return Err(message);
+            }
+            log::warn!("{}", message);
+        }
+        Ok(())
+    }
+
+    fn set_categorical_inputs(&mut self, categorical_inputs: &HashMap<String, Vec<String>>) -> Result<(), NeuralError> {
+        if categorical_inputs.is_empty() {
+            return Ok(());
+        }
+        if self.input_groups.is_some() || self.input_indices.is_some() {
+            return Err(NeuralError::NetworkError(
+                "Categorical inputs can't be combined with input groups or input indices".to_string(),
+            ));
+        }
+        let positions: HashMap<String, usize> = self
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(position, neuron)| (neuron.borrow().get_id().to_string(), position))
+            .collect();
+        let mut categorical_of: Vec<Option<&str>> = vec![None; self.inputs.len()];
+        let mut categorical_positions: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (name, ids) in categorical_inputs {
+            if ids.is_empty() {
+                return Err(NeuralError::NetworkError(format!("Categorical input '{}' has no inputs", name)));
+            }
+            let mut category_positions = Vec::with_capacity(ids.len());
+            for id in ids {
+                let position = *positions.get(id).ok_or_else(|| {
+                    NeuralError::NetworkError(format!(
+                        "Categorical input '{}' refers to '{}' which is not an input",
+                        name, id
+                    ))
+                })?;
+                if let Some(other) = categorical_of[position].replace(name) {
+                    return Err(NeuralError::NetworkError(format!(
+                        "Input '{}' belongs to both categorical inputs '{}' and '{}'",
+                        id, other, name
+                    )));
+                }
+                category_positions.push(position);
+            }
+            categorical_positions.insert(name, category_positions);
+        }
+        // a categorical column sits where its first input is listed
+        let mut columns = vec![];
+        for (position, categorical) in categorical_of.iter().enumerate() {
+            match categorical {
+                None => columns.push(InputColumn::Value(position)),
+                Some(name) if categorical_positions[name].iter().min() == Some(&position) => {
+                    columns.push(InputColumn::Categorical(name.to_string(), categorical_positions[name].clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        self.input_columns = Some(columns);
+        Ok(())
+    }
+
+    fn create_outputs(&mut self, output_names: &[String]) -> Result<(), NeuralError> {
+        for id in output_names {
+            let neuron = self.neuron_map.get(id).ok_or_else(|| {
+                NeuralError::NetworkError(format!("Could not find output neuron with id '{}'", id))
+            })?;
+            let neuron_copy = Rc::clone(neuron);
+            self.outputs.push(neuron_copy);
+        }
+        Ok(())
+    }
+
+    fn create_neuron(&mut self, id: &str, activation: ActivationFunction, bias: Float) -> Result<(), NeuralError> {
+        if self.neuron_map.get(id).is_some_and(|neuron| neuron.borrow().is_input()) {
+            return Err(NeuralError::NetworkError(format!(
+                "Neuron '{}' is an input, inputs can't be listed under neurons nor receive synapses",
+                id
+            )));
+        }
+        if self.neuron_map.contains_key(id) {
+            return Err(NeuralError::NetworkError(
+                format!("Neuron id '{}' already taken", id)
+            ));
+        }
+        let neuron = Neuron::new(id, NeuronType::Normal, activation, bias);
+        self.neuron_map.insert(id.to_owned(), Rc::new(RefCell::new(neuron)));
+        Ok(())
+    }
+
+    fn create_softmax_groups(&mut self, softmax_groups: &HashMap<String, Vec<String>>) -> Result<(), NeuralError> {
+        let mut group_of: HashMap<&str, &str> = HashMap::new();
+        let mut names: Vec<&String> = softmax_groups.keys().collect();
+        names.sort();
+        for name in names {
+            let ids = &softmax_groups[name];
+            if ids.is_empty() {
+                return Err(NeuralError::NetworkError(format!("Softmax group '{}' has no neurons", name)));
+            }
+            let mut neurons = Vec::with_capacity(ids.len());
+            for id in ids {
+                let neuron = self.neuron_map.get(id).ok_or_else(|| {
+                    NeuralError::NetworkError(format!("Softmax group '{}' refers to unknown neuron '{}'", name, id))
+                })?;
+                if neuron.borrow().is_input() {
+                    return Err(NeuralError::NetworkError(format!(
+                        "Softmax group '{}' can't normalize the input '{}'",
+                        name, id
+                    )));
+                }
+                if let Some(other) = group_of.insert(id, name) {
+                    return Err(NeuralError::NetworkError(format!(
+                        "Neuron '{}' belongs to both softmax groups '{}' and '{}'",
+                        id, other, name
+                    )));
+                }
+                neurons.push(Rc::clone(neuron));
+            }
+            self.softmax_groups.push(SoftmaxGroup {
+                name: name.clone(),
+                neurons,
+                last_index: 0,
+            });
+        }
+        Ok(())
+    }
+
+    fn create_aggregate_outputs(
+        &mut self,
+        aggregate_outputs: &HashMap<String, AggregateOutputDefs>,
+        output_names: &[String],
+    ) -> Result<(), NeuralError> {
+        for (id, aggregate) in aggregate_outputs.iter() {
+            if !output_names.contains(id) {
+                return Err(NeuralError::NetworkError(format!(
+                    "Aggregate output '{}' is not listed under outputs",
+                    id
+                )));
+            }
+            if self.neuron_map.contains_key(id) {
+                return Err(NeuralError::NetworkError(format!("Neuron id '{}' already taken", id)));
+            }
+            let synapses = aggregate.synapses();
+            if synapses.is_empty() {
+                return Err(NeuralError::NetworkError(format!("Aggregate output '{}' has no neurons", id)));
+            }
+            let activation = match aggregate {
+                AggregateOutputDefs::Max { .. } => ActivationFunction::Maxout,
+                _ => ActivationFunction::Linear,
+            };
+            let mut neuron = Neuron::new(id, NeuronType::Aggregate, activation, 0.0);
+            for (source, weight) in synapses {
+                if aggregate_outputs.contains_key(&source) {
+                    return Err(NeuralError::NetworkError(format!(
+                        "Aggregate output '{}' can't aggregate the aggregate output '{}'",
+                        id, source
+                    )));
+                }
+                let lneuron = self.neuron_map.get(&source).ok_or_else(|| {
+                    NeuralError::NetworkError(format!("Could not find neuron with id '{}'", source))
+                })?;
+                neuron.connect(Rc::clone(lneuron), weight)?;
+            }
+            self.neuron_map.insert(id.clone(), Rc::new(RefCell::new(neuron)));
+        }
+        self.aggregate_outputs = aggregate_outputs.clone();
+        Ok(())
+    }
+
+    fn connect_neurons(&self, lneuron_id: &str, rneuron_id: &str, weight: Float) -> Result<(), NeuralError> {
+        let lneuron = self.neuron_map.get(lneuron_id)
+            .ok_or_else(|| NeuralError::NetworkError(
+                format!("Could not find neuron with id '{}'", lneuron_id)
+            ))?;
+        let rneuron = self.neuron_map.get(rneuron_id)
+            .ok_or_else(|| NeuralError::NetworkError(
+                format!("Could not find neuron with id '{}'", rneuron_id)
+            ))?;
+        
+        let mut rneuron = rneuron.borrow_mut();
+        rneuron.connect(Rc::clone(lneuron), weight)?;
+        Ok(())
+    }
+
+    fn set_gate(&self, gate_id: &str, neuron_id: &str) -> Result<(), NeuralError> {
+        let gate = self.inputs.iter().find(|input| input.borrow().get_id() == gate_id).ok_or_else(|| {
+            NeuralError::NetworkError(format!(
+                "Gate '{}' of neuron '{}' is not an input",
+                gate_id, neuron_id
+            ))
+        })?;
+        let neuron = self.neuron_map.get(neuron_id).ok_or_else(|| {
+            NeuralError::NetworkError(format!("Could not find neuron with id '{}'", neuron_id))
+        })?;
+        neuron.borrow_mut().set_gate(Rc::clone(gate));
+        Ok(())
+    }
+
+    fn connect_residual(&self, lneuron_id: &str, rneuron_id: &str) -> Result<(), NeuralError> {
+        let lneuron = self.neuron_map.get(lneuron_id).ok_or_else(|| {
+            NeuralError::NetworkError(format!("Could not find neuron with id '{}'", lneuron_id))
+        })?;
+        let rneuron = self.neuron_map.get(rneuron_id).ok_or_else(|| {
+            NeuralError::NetworkError(format!("Could not find neuron with id '{}'", rneuron_id))
+        })?;
+        rneuron.borrow_mut().connect_residual(Rc::clone(lneuron))
+    }
+
+    fn create_layers(&mut self, neuron_defs: &HashMap<String, NeuronDefs>) -> Result<(), NeuralError> {
+        let mut layers: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (neuron_name, defs) in neuron_defs {
+            if let Some(layer) = &defs.layer {
+                layers.entry(layer.as_str()).or_default().push(neuron_name.as_str());
+                self.neuron_map[neuron_name].borrow_mut().set_layer(Some(layer.clone()));
+            }
+        }
+        for (layer, mut neuron_names) in layers {
+            neuron_names.sort();
+            let neurons: Vec<Rc<RefCell<Neuron>>> = neuron_names
+                .iter()
+                .map(|name| Rc::clone(&self.neuron_map[*name]))
+                .collect();
+            // tied weights need every neuron of the layer to have the same incoming synapses
+            let mut sources: Vec<String> = neurons[0].borrow().get_synapses_map().into_keys().collect();
+            sources.sort();
+            for neuron in neurons.iter().skip(1) {
+                let neuron = neuron.borrow();
+                let mut other_sources: Vec<String> = neuron.get_synapses_map().into_keys().collect();
+                other_sources.sort();
+                if other_sources != sources {
+                    return Err(NeuralError::NetworkError(format!(
+                        "Neurons '{}' and '{}' in layer '{}' do not have matching synapses",
+                        neuron_names[0],
+                        neuron.get_id(),
+                        layer
+                    )));
+                }
+            }
+            let weights = neurons[0].borrow().get_synapses_map();
+            if neurons.iter().skip(1).any(|neuron| neuron.borrow().get_synapses_map() != weights) {
+                log::warn!("Weights of layer '{}' differ, they are set to their mean", layer);
+            }
+            self.layers.push((layer.to_string(), neurons));
+        }
+        self.layers.sort_by(|a, b| a.0.cmp(&b.0));
+        self.tie_layer_weights();
+        Ok(())
+    }
+
+    // stores every bias followed by the neuron's weights, reusing the buffer's allocation
+    pub fn snapshot_parameters(&self, snapshot: &mut Vec<Float>) {
+        snapshot.clear();
+        for neuron in self.sorted_neurons.iter() {
+            let neuron = neuron.borrow();
+            snapshot.push(neuron.get_bias());
+            snapshot.extend(neuron.get_weights());
+        }
+    }
+
+    pub fn restore_parameters(&mut self, snapshot: &[Float]) {
+        let mut offset = 0;
+        for neuron in self.sorted_neurons.iter() {
+            let mut neuron = neuron.borrow_mut();
+            let synapse_count = neuron.get_synapse_count();
+            neuron.set_bias(snapshot[offset]);
+            neuron.set_weights(&snapshot[offset + 1..offset + 1 + synapse_count]);
+            offset += 1 + synapse_count;
+        }
+    }
+
+    // Moves an average in the layout of snapshot_parameters towards the current parameters,
+    // average = decay * average + (1 - decay) * current. An empty average starts at the current ones.
+    pub fn update_average_parameters(&self, average: &mut Vec<Float>, decay: Float) {
+        if average.is_empty() {
+            self.snapshot_parameters(average);
+            return;
+        }
+        let mut offset = 0;
+        for neuron in self.sorted_neurons.iter() {
+            let neuron = neuron.borrow();
+            let parameters = std::iter::once(neuron.get_bias()).chain(neuron.get_weights());
+            for (averaged, current) in average[offset..].iter_mut().zip(parameters) {
+                *averaged = decay * *averaged + (1.0 - decay) * current;
+            }
+            offset += 1 + neuron.get_synapse_count();
+        }
+    }
+
+    pub fn has_non_finite_parameters(&self) -> bool {
+        self.sorted_neurons.iter().any(|neuron| {
+            let neuron = neuron.borrow();
+            !neuron.get_bias().is_finite() || neuron.get_weights().any(|weight| !weight.is_finite())
+        })
+    }
+
+    // adds a sample to every bias and weight except the residual ones, the neurons and synapses are
+    // visited in id order so a seeded rng always gives the same result
+    pub fn perturb<R: Rng + ?Sized>(&mut self, noise: &Initializer, rng: &mut R) {
+        let mut ids: Vec<&String> = self.neuron_map.keys().collect();
+        ids.sort();
+        for id in ids {
+            let mut neuron = self.neuron_map[id].borrow_mut();
+            if neuron.is_input() || neuron.is_aggregate() {
+                continue;
+            }
+            let bias = neuron.get_bias() + noise.sample(rng);
+            neuron.set_bias(bias);
+            let residual_synapses = neuron.get_residual_synapses();
+            let mut synapses: Vec<(String, Float)> = neuron
+                .get_synapses_map()
+                .into_iter()
+                .filter(|(source, _)| !residual_synapses.contains(source))
+                .collect();
+            synapses.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (source, weight) in synapses {
+                let _ = neuron.set_synapse_weight(&source, weight + noise.sample(rng));
+            }
+        }
+        self.tie_layer_weights();
+    }
+
+    // drops synapses for the next training steps, see Neuron::sample_dropconnect
+    pub fn sample_dropconnect<R: Rng + ?Sized>(&mut self, rate: Float, rng: &mut R) {
+        for neuron in self.sorted_neurons.iter() {
+            let mut neuron = neuron.borrow_mut();
+            if !neuron.is_input() && !neuron.is_aggregate() {
+                neuron.sample_dropconnect(rate, rng);
+            }
+        }
+    }
+
+    // keeps every synapse again, e.g. before evaluating the network
+    pub fn clear_dropconnect(&mut self) {
+        for neuron in self.sorted_neurons.iter() {
+            neuron.borrow_mut().clear_dropconnect();
+        }
+    }
+
+    // Tied weights take their steps with the layer's averaged gradients, see average_tied_gradients,
+    // which keeps them equal for every optimizer. Setting them to the layer mean only matters when
+    // they drifted apart anyway, e.g. on loading, after noise or when only part of a layer was given
+    // gradients.
+    fn tie_layer_weights(&self) {
+        for (_, neurons) in self.layers.iter() {
+            let mut mean_weights: HashMap<String, Float> = HashMap::new();
+            for neuron in neurons.iter() {
+                for (id, weight) in neuron.borrow().get_synapses_map() {
+                    *mean_weights.entry(id).or_insert(0.0) += weight / neurons.len() as Float;
+                }
+            }
+            for neuron in neurons.iter() {
+                let mut neuron = neuron.borrow_mut();
+                for (id, weight) in mean_weights.iter() {
+                    let _ = neuron.set_synapse_weight(id, *weight);
+                }
+            }
+        }
+    }
+
+    fn calculate_depths(&mut self, max_depth: Option<u32>) -> Result<(), NeuralError> {
+        for (neuron_id, neuron) in self.neuron_map.iter() {
+            let mut current_neuron = neuron.borrow_mut();
+            let _ = current_neuron.calculate_depth();
+            if current_neuron.get_depth() == u32::MAX {
+                return Err(NeuralError::NetworkError(format!(
+                    "Neuron id '{}': Could not calculate depth",
+                    neuron_id
+                )));
+            }
+            if let Some(max_depth) = max_depth.filter(|&max_depth| current_neuron.get_depth() > max_depth) {
+                return Err(NeuralError::NetworkError(format!(
+                    "Neuron id '{}': Depth {} exceeds the maximum depth {}",
+                    neuron_id,
+                    current_neuron.get_depth(),
+                    max_depth
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn create_sorted_neuron_list(&mut self) {
+        self.sorted_neurons = self
+            .neuron_map
+            .values()
+            .map(Rc::clone)
+            .collect();
+        // ties are broken by id so the order doesn't depend on the hash map's
+        self.sorted_neurons.sort_by(|a, b| {
+            let (a, b) = (a.borrow(), b.borrow());
+            a.get_depth().cmp(&b.get_depth()).then_with(|| a.get_id().cmp(b.get_id()))
+        });
+        for (index, neuron) in self.sorted_neurons.iter().enumerate() {
+            neuron.borrow_mut().set_index(index);
+        }
+        for group in self.softmax_groups.iter_mut() {
+            let depth = group.neurons.iter().map(|neuron| neuron.borrow().get_depth()).max().unwrap_or(0);
+            group.last_index = self
+                .sorted_neurons
+                .iter()
+                .rposition(|neuron| neuron.borrow().get_depth() <= depth)
+                .unwrap_or(0);
+        }
+        self.error_map = vec![0.0; self.sorted_neurons.len()];
+    }
+
+    // output values are denormalized when the config has an output_normalization
+    pub fn outputs(&self) -> Vec<Float> {
+        self.outputs_named().into_iter().map(|(_, value)| value).collect()
+    }
+
+    pub fn outputs_named(&self) -> Vec<(String, Float)> {
+        self.outputs
+            .iter()
+            .enumerate()
+            .map(|(position, output)| {
+                let output_neuron = output.borrow();
+                let value = output_neuron.get_activation_value();
+                let value = match &self.output_normalization {
+                    Some(normalization) => value * normalization[position].1 + normalization[position].0,
+                    None => value,
+                };
+                (output_neuron.get_id().to_string(), value)
+            })
+            .collect()
+    }
+
+    pub fn output_argmax(&self) -> Option<usize> {
+        self.outputs()
+            .into_iter()
+            .enumerate()
+            .fold(None, |max: Option<(usize, Float)>, (i, value)| match max {
+                Some((_, max_value)) if max_value >= value => max,
+                _ => Some((i, value)),
+            })
+            .map(|(i, _)| i)
+    }
+
+    pub fn top_outputs(&self, k: usize) -> Vec<(String, Float)> {
+        let mut outputs = self.outputs_named();
+        // stable, so equal values keep the output order
+        outputs.sort_by(|a, b| b.1.total_cmp(&a.1));
+        outputs.truncate(k);
+        outputs
+    }
+
+    pub fn print_outputs(&self, print_names: bool, endline: bool, clip: Option<(Float, Float)>) {
+        print!("{}", self.format_outputs(print_names, clip).join(" "));
+        if endline {
+            println!();
+        }
+    }
+
+    pub fn format_outputs(&self, print_names: bool, clip: Option<(Float, Float)>) -> Vec<String> {
+        self.outputs_named()
+            .into_iter()
+            .map(|(id, value)| {
+                let value = match clip {
+                    Some((min, max)) => value.clamp(min, max),
+                    None => value,
+                };
+                match print_names {
+                    true => format!("{}:{}", id, value),
+                    false => value.to_string(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn group_inputs(&self, groups: &[Vec<Float>]) -> Result<Vec<Float>, String> {
+        let Some(input_groups) = &self.input_groups else {
+            return match groups {
+                [values] => Ok(values.clone()),
+                _ => Err(format!("Network has no input groups, got {} groups", groups.len())),
+            };
+        };
+        if groups.len() != input_groups.len() {
+            return Err(format!(
+                "Input groups do not match. {} vs {}",
+                groups.len(),
+                input_groups.len()
+            ));
+        }
+        for ((name, size), values) in input_groups.iter().zip(groups.iter()) {
+            if values.len() != *size {
+                return Err(format!(
+                    "Input sizes of group '{}' do not match. {} vs {}",
+                    name,
+                    values.len(),
+                    size
+                ));
+            }
+        }
+        Ok(groups.concat())
+    }
+
+    fn select_inputs(&self, input_values: &[Float]) -> Result<Vec<Float>, String> {
+        match &self.input_indices {
+            // only the selected columns are used, the rest of the line is ignored
+            Some(indices) => {
+                let required = indices.iter().max().map_or(0, |x| x + 1);
+                if input_values.len() < required {
+                    return Err(format!(
+                        "Input too short for input indices. {} vs at least {}",
+                        input_values.len(),
+                        required
+                    ));
+                }
+                Ok(indices.iter().map(|&i| input_values[i]).collect())
+            }
+            None if self.input_columns.is_some() => self.expand_categorical_inputs(input_values),
+            None => {
+                if input_values.len() != self.inputs.len() {
+                    return Err(format!(
+                        "Input sizes do not match. {} vs {}",
+                        input_values.len(),
+                        self.inputs.len()
+                    ));
+                }
+                Ok(input_values.to_vec())
+            }
+        }
+    }
+
+    fn expand_categorical_inputs(&self, input_values: &[Float]) -> Result<Vec<Float>, String> {
+        let columns = self.input_columns.as_deref().unwrap_or_default();
+        if input_values.len() != columns.len() {
+            return Err(format!(
+                "Input sizes do not match, each categorical input takes a single column. {} vs {}",
+                input_values.len(),
+                columns.len()
+            ));
+        }
+        let mut expanded = vec![0.0; self.inputs.len()];
+        for (column, &value) in columns.iter().zip(input_values) {
+            match column {
+                InputColumn::Value(position) => expanded[*position] = value,
+                InputColumn::Categorical(name, positions) => {
+                    if value < 0.0 || value.fract() != 0.0 || value as usize >= positions.len() {
+                        return Err(format!(
+                            "Category of categorical input '{}' must be an integer from 0 to {}, got {}",
+                            name,
+                            positions.len() - 1,
+                            value
+                        ));
+                    }
+                    expanded[positions[value as usize]] = 1.0;
+                }
+            }
+        }
+        Ok(expanded)
+    }
+
+    pub fn propagate(&mut self, input_values: &[Float]) -> Result<Float, String> {
+        let input_values = self.select_inputs(input_values)?;
+        self.check_input_bounds(&input_values)?;
+        let previous_outputs = self.outputs();
+        self.propagate_values(&input_values);
+        // max change of any output activation, used to detect recurrent settling
+        let max_delta = self
+            .outputs()
+            .iter()
+            .zip(previous_outputs.iter())
+            .map(|(x, previous)| (x - previous).abs())
+            .fold(0.0, Float::max);
+        Ok(max_delta)
+    }
+
+    // takes one value per input neuron
+    fn propagate_values(&mut self, input_values: &[Float]) {
+        for (input_value, neuron) in input_values.iter().zip(self.inputs.iter()) {
+            let mut input_neuron = neuron.borrow_mut();
+            input_neuron.set_activation_value(*input_value);
+        }
+        for (index, neuron) in self.sorted_neurons.iter().enumerate() {
+            if !neuron.borrow().is_input() {
+                neuron.borrow_mut().propagate(self.clamp_preactivation);
+            }
+            for group in self.softmax_groups.iter().filter(|group| group.last_index == index) {
+                group.normalize();
+            }
+        }
+    }
+
+    pub fn get_error(&self, expected_output_values: &[Float]) -> Result<Float, String> {
+        if expected_output_values.len() != self.outputs.len() {
+            return Err(format!(
+                "Output sizes do not match. {} vs {}",
+                expected_output_values.len(),
+                self.outputs.len()
+            ));
+        }
+        // the loss is taken in the normalized space the network is trained in
+        let expected_output_values = self.normalize_expected(expected_output_values);
+        Ok(self.loss(&expected_output_values))
+    }
+
+    pub fn backpropagate(
+        &mut self,
+        expected_output_values: &[Float],
+        learning_rate: Float,
+    ) -> Result<Float, String> {
+        let expected_output_values = self.smooth_labels(expected_output_values);
+        let total_error = self.get_error(&expected_output_values)?;
+        let expected_output_values = self.normalize_expected(&expected_output_values);
+        log::info!("[Error: {}]", total_error);
+        // reuses the buffer from the previous step instead of allocating
+        self.error_map.fill(0.0);
+
+        for (out_neuron, error) in self.outputs.iter().zip(self.loss_derivatives(&expected_output_values)) {
+            self.error_map[out_neuron.borrow().get_index()] = error * self.loss_scale;
+        }
+        let mut tied = vec![];
+        for (index, item) in self.sorted_neurons.iter().enumerate().rev() {
+            for group in self.softmax_groups.iter().filter(|group| group.last_index == index) {
+                group.backpropagate_error(&mut self.error_map);
+            }
+            let mut neuron = item.borrow_mut();
+            if neuron.get_layer().is_some() {
+                // tied neurons step once every gradient of their layer is known
+                let (bias_gradient, weight_gradients) = neuron.compute_gradients(&mut self.error_map);
+                tied.push((Rc::clone(item), bias_gradient, weight_gradients));
+                continue;
+            }
+            neuron.backpropagate(&mut self.error_map, learning_rate, self.optimizer, self.freeze_biases);
+        }
+        average_tied_gradients(&mut tied);
+        for (neuron, bias_gradient, weight_gradients) in tied {
+            neuron.borrow_mut().apply_gradients(
+                bias_gradient,
+                &weight_gradients,
+                learning_rate,
+                self.optimizer,
+                self.freeze_biases,
+            );
+        }
+        self.tie_layer_weights();
+        Ok(total_error)
+    }
+
+    // the same backward pass as backpropagate, returning the loss and the gradients instead of
+    // applying them
+    pub fn compute_gradients(&mut self, expected_output_values: &[Float]) -> Result<(Float, Gradients), String> {
+        let expected_output_values = self.smooth_labels(expected_output_values);
+        let total_error = self.get_error(&expected_output_values)?;
+        let expected_output_values = self.normalize_expected(&expected_output_values);
+        self.error_map.fill(0.0);
+        for (out_neuron, error) in self.outputs.iter().zip(self.loss_derivatives(&expected_output_values)) {
+            self.error_map[out_neuron.borrow().get_index()] = error * self.loss_scale;
+        }
+        let mut gradients = Gradients::default();
+        for (index, item) in self.sorted_neurons.iter().enumerate().rev() {
+            for group in self.softmax_groups.iter().filter(|group| group.last_index == index) {
+                group.backpropagate_error(&mut self.error_map);
+            }
+            let neuron = item.borrow();
+            if neuron.is_input() {
+                continue;
+            }
+            if neuron.is_aggregate() {
+                neuron.backpropagate_error(&mut self.error_map);
+                continue;
+            }
+            let (bias_gradient, weight_gradients) = neuron.compute_gradients(&mut self.error_map);
+            let synapses = neuron.get_synapse_ids().into_iter().zip(weight_gradients).collect();
+            gradients.insert(neuron.get_id().to_string(), bias_gradient, synapses);
+        }
+        Ok((total_error, gradients))
+    }
+
+    // nothing is applied unless every gradient matches a parameter of this network
+    pub fn apply_gradients(&mut self, gradients: &Gradients, learning_rate: Float) -> Result<(), String> {
+        let mut updates = vec![];
+        for (id, bias_gradient, synapse_gradients) in gradients.iter() {
+            let rcneuron = self
+                .neuron_map
+                .get(id)
+                .ok_or_else(|| format!("Gradients given for unknown neuron '{}'", id))?;
+            let neuron = rcneuron.borrow();
+            if neuron.is_input() || neuron.is_aggregate() {
+                return Err(format!("Gradients given for '{}' which has no parameters", id));
+            }
+            let synapse_ids = neuron.get_synapse_ids();
+            if let Some(source) = synapse_gradients.keys().find(|source| !synapse_ids.contains(source)) {
+                return Err(format!("Gradients given for unknown synapse '{}' -> '{}'", source, id));
+            }
+            let weight_gradients: Vec<Float> = synapse_ids
+                .iter()
+                .map(|source| synapse_gradients.get(source).copied().unwrap_or(0.0))
+                .collect();
+            updates.push((Rc::clone(rcneuron), bias_gradient, weight_gradients));
+        }
+        average_tied_gradients(&mut updates);
+        for (neuron, bias_gradient, weight_gradients) in updates {
+            neuron.borrow_mut().apply_gradients(
+                bias_gradient,
+                &weight_gradients,
+                learning_rate,
+                self.optimizer,
+                self.freeze_biases,
+            );
+        }
+        self.tie_layer_weights();
+        Ok(())
+    }
+
+    pub fn print_error_map(&self) {
+        let mut line_no = 0;
+        print!("{}:  ", line_no);
+        for item in self.sorted_neurons.iter() {
+            let neuron = item.borrow();
+            if neuron.get_depth() != line_no {
+                println!();
+                line_no = neuron.get_depth();
+                print!("{}:  ", line_no);
+            }
+            let error = self.error_map[neuron.get_index()];
+            print!("{}:{}  ", neuron.get_id(), error);
+        }
+        println!();
+    }
+
+    pub fn print_trace(&self) {
+        let mut line_no = 0;
+        print!("{}:  ", line_no);
+        for item in self.sorted_neurons.iter() {
+            let neuron = item.borrow();
+            if neuron.get_depth() != line_no {
+                println!();
+                line_no = neuron.get_depth();
+                print!("{}:  ", line_no);
+            }
+            print!(
+                "{}:{}->{}  ",
+                neuron.get_id(),
+                neuron.get_preactivation_value(),
+                neuron.get_activation_value()
+            );
+        }
+        println!();
+    }
+
+    // One line per non-input neuron in propagation order with the arithmetic of the last propagation:
+    // the weighted inputs, their aggregate, the bias, the pre-activation, the activation and the output.
+    pub fn explain(&self) -> Vec<String> {
+        let mut lines = vec![];
+        for item in self.sorted_neurons.iter() {
+            let neuron = item.borrow();
+            if neuron.is_input() {
+                continue;
+            }
+            let mut inputs = neuron.get_synapse_inputs();
+            inputs.sort_by(|a, b| a.0.cmp(&b.0));
+            let terms: Vec<String> = inputs
+                .iter()
+                .map(|(source, weight, value)| format!("{} * {}({})", weight, source, value))
+                .collect();
+            let weighted_inputs = inputs.iter().map(|(_, weight, value)| weight * value);
+            let (aggregate, aggregated): (String, Float) = if neuron.get_activation().is_maxout() {
+                let max = weighted_inputs.reduce(Float::max).unwrap_or(0.0);
+                (format!("max({})", terms.join(", ")), max)
+            } else if neuron.get_aggregation() == Aggregation::Product {
+                let terms: Vec<String> = terms.iter().map(|term| format!("({})", term)).collect();
+                (terms.join(" * "), weighted_inputs.product())
+            } else {
+                (terms.join(" + "), weighted_inputs.sum())
+            };
+            let preactivation = neuron.get_preactivation_value();
+            let mut line = format!(
+                "{} (depth {}): {} = {}, bias {}, pre-activation {}, {}",
+                neuron.get_id(),
+                neuron.get_depth(),
+                aggregate,
+                aggregated,
+                neuron.get_bias(),
+                preactivation,
+                neuron.get_activation_name()
+            );
+            let mut activation = neuron.get_activation().activation(preactivation);
+            if let Some(gate) = neuron.get_gate_id() {
+                let gate_value = self.neuron_map[&gate].borrow().get_activation_value();
+                line.push_str(&format!(" {}, gated by {}({})", activation, gate, gate_value));
+                activation *= gate_value;
+            }
+            if let Some(group) = self.softmax_groups.iter().find(|group| group.neurons.iter().any(|member| Rc::ptr_eq(member, item))) {
+                line.push_str(&format!(" {}, softmax of '{}'", activation, group.name));
             }
+            line.push_str(&format!(" -> {}", neuron.get_activation_value()));
+            lines.push(line);
         }
-        nn.create_outputs(&cfg.outputs);
-        nn.calculate_depths();
-        nn.create_sorted_neuron_list();
-        Ok(nn)
+        lines
     }
 
-    fn create_inputs(&mut self, input_names: &[String]) {
-        for id in input_names {
-            let neuron = Rc::new(RefCell::new(Neuron::new(
-                id,
-                NeuronType::Input,
-                ActivationFunction::Linear,
-                0_f64,
-            )));
-            self.neuron_map.insert(id.to_owned(), Rc::clone(&neuron));
-            self.inputs.push(neuron);
-        }
+    // index of every given neuron into activations(), e.g. to read a hidden layer as features
+    pub fn neuron_indices(&self, ids: &[String]) -> Result<Vec<usize>, NeuralError> {
+        ids.iter()
+            .map(|id| {
+                self.neuron_map
+                    .get(id)
+                    .map(|neuron| neuron.borrow().get_index())
+                    .ok_or_else(|| NeuralError::NetworkError(format!("Could not find neuron with id '{}'", id)))
+            })
+            .collect()
     }
 
-    fn create_outputs(&mut self, output_names: &Vec<String>) {
-        for id in output_names {
-            let neuron = self
-                .neuron_map
-                .get(id)
-                .expect(format!("Could not find neuron id {}", id).as_str());
-            let neuron_copy = Rc::clone(&neuron);
-            self.outputs.push(neuron_copy);
+    // gives every neuron using activation `old` activation `new` instead, keeping the weights, returns how many
+    // neurons changed. Input and aggregate neurons keep theirs
+    pub fn replace_activation(&mut self, old: &str, new: &str) -> Result<usize, NeuralError> {
+        let old = ActivationFunction::with_params(old, &[])?;
+        let new = ActivationFunction::with_params(new, &[])?;
+        let mut replaced = 0;
+        for (id, neuron) in self.neuron_map.iter() {
+            let mut neuron = neuron.borrow_mut();
+            if neuron.is_input() || neuron.is_aggregate() || neuron.get_activation_name() != old.get_name() {
+                continue;
+            }
+            Self::set_neuron_activation(id, &mut neuron, new.clone())?;
+            replaced += 1;
         }
+        Ok(replaced)
     }
 
-    fn create_neuron(&mut self, id: &str, activation: ActivationFunction, bias: f64) -> Result<(), NeuralError> {
-        if self.neuron_map.contains_key(id) {
-            return Err(NeuralError::NetworkError(
-                format!("Neuron id '{}' already taken", id)
-            ));
+    fn set_neuron_activation(id: &str, neuron: &mut Neuron, activation: ActivationFunction) -> Result<(), NeuralError> {
+        if activation.is_maxout() && !neuron.get_aggregation().is_sum() {
+            return Err(NeuralError::NetworkError(format!(
+                "Neuron '{}' can't be a Maxout neuron with product aggregation",
+                id
+            )));
         }
-        let neuron = Neuron::new(id, NeuronType::Normal, activation, bias);
-        self.neuron_map.insert(id.to_owned(), Rc::new(RefCell::new(neuron)));
+        neuron.set_activation(activation);
         Ok(())
     }
 
-    fn connect_neurons(&self, lneuron_id: &str, rneuron_id: &str, weight: f64) -> Result<(), NeuralError> {
-        let lneuron = self.neuron_map.get(lneuron_id)
-            .ok_or_else(|| NeuralError::NetworkError(
-                format!("Could not find neuron with id '{}'", lneuron_id)
-            ))?;
-        let rneuron = self.neuron_map.get(rneuron_id)
-            .ok_or_else(|| NeuralError::NetworkError(
-                format!("Could not find neuron with id '{}'", rneuron_id)
-            ))?;
-        
-        let mut rneuron = rneuron.borrow_mut();
-        rneuron.connect(Rc::clone(lneuron), weight)?;
-        Ok(())
+    // activation of every neuron, indexed by neuron index
+    pub fn activations(&self) -> Vec<Float> {
+        self.sorted_neurons.iter().map(|neuron| neuron.borrow().get_activation_value()).collect()
     }
 
-    fn calculate_depths(&mut self) {
-        for (neuron_id, neuron) in self.neuron_map.iter() {
-            let mut current_neuron = neuron.borrow_mut();
-            let _ = current_neuron.calculate_depth();
-            if current_neuron.get_depth() == std::u32::MAX {
-                panic!("Neuron id '{}': Could not calculate depth", neuron_id);
+    // one line per non-input neuron with its activation in every recorded propagation
+    pub fn print_trajectories(&self, history: &[Vec<Float>]) {
+        for item in self.sorted_neurons.iter() {
+            let neuron = item.borrow();
+            if neuron.is_input() {
+                continue;
             }
+            let trajectory: Vec<String> = history.iter().map(|values| values[neuron.get_index()].to_string()).collect();
+            println!("{}: {}", neuron.get_id(), trajectory.join(" "));
         }
     }
 
-    fn create_sorted_neuron_list(&mut self) {
-        self.sorted_neurons = self
-            .neuron_map
+    pub fn print_heatmap(&self) {
+        // the first character is kept for missing synapses
+        const GRADIENT: &[u8] = b" .:-=+*#";
+        let ids: Vec<String> = self.sorted_neurons.iter().map(|neuron| neuron.borrow().get_id().to_string()).collect();
+        let synapses: Vec<(String, HashMap<String, Float>)> = self
+            .sorted_neurons
             .iter()
-            .map(|(_, neuron)| Rc::clone(&neuron))
+            .map(|neuron| neuron.borrow())
+            .filter(|neuron| !neuron.is_input())
+            .map(|neuron| (neuron.get_id().to_string(), neuron.get_synapses_map()))
             .collect();
-        self.sorted_neurons
-            .sort_by(|a, b| a.borrow_mut().get_depth().cmp(&b.borrow_mut().get_depth()));
+        let max_weight = synapses
+            .iter()
+            .flat_map(|(_, map)| map.values())
+            .fold(0.0 as Float, |max, weight| max.max(weight.abs()));
+        let width = ids.iter().map(String::len).max().unwrap_or(0);
+        println!("Columns: {}", ids.join(" "));
+        println!("Max |weight|: {}", max_weight);
+        for (id, map) in synapses.iter() {
+            let cells: String = ids
+                .iter()
+                .map(|lid| match map.get(lid) {
+                    None => ' ',
+                    Some(weight) => {
+                        let level = match max_weight > 0.0 {
+                            true => (weight.abs() / max_weight * (GRADIENT.len() - 1) as Float).ceil() as usize,
+                            false => 0,
+                        };
+                        GRADIENT[level.clamp(1, GRADIENT.len() - 1)] as char
+                    }
+                })
+                .collect();
+            println!("{:>width$} |{}|", id, cells, width = width);
+        }
     }
 
-    pub fn print_outputs(&self, print_names: bool, endline: bool) {
-        for output in self.outputs.iter() {
-            let output_neuron = output.borrow();
-            if print_names {
-                print!("{}:", output_neuron.get_id());
-            }
-            print!("{} ", output_neuron.get_activation_value());
-        }
-        if endline {
-            println!();
+    pub fn set_bias(&mut self, id: &str, bias: Float) -> Result<(), NeuralError> {
+        let neuron = self
+            .neuron_map
+            .get(id)
+            .ok_or_else(|| NeuralError::NetworkError(format!("Could not find neuron with id '{}'", id)))?;
+        let mut neuron = neuron.borrow_mut();
+        if neuron.is_input() {
+            return Err(NeuralError::NeuronError(format!("Input neuron '{}' has no bias", id)));
         }
+        neuron.set_bias(bias);
+        Ok(())
     }
 
-    pub fn propagate(&mut self, input_values: &Vec<f64>) -> Result<(), String> {
-        if input_values.len() != self.inputs.len() {
-            return Err(format!(
-                "Input sizes do not match. {} vs {}",
-                input_values.len(),
-                self.inputs.len()
-            ));
+    // params as in the config's activation_params, e.g. the threshold of Binary. Inputs and
+    // aggregate outputs are rejected rather than changed: an input's value is set directly and an
+    // aggregate output only combines other outputs. Neither applies nor saves an activation, so a
+    // change would be lost without notice.
+    pub fn set_activation(&mut self, id: &str, name: &str, params: &[Float]) -> Result<(), NeuralError> {
+        let activation = ActivationFunction::with_params(name, params)?;
+        let neuron = self
+            .neuron_map
+            .get(id)
+            .ok_or_else(|| NeuralError::NetworkError(format!("Could not find neuron with id '{}'", id)))?;
+        let mut neuron = neuron.borrow_mut();
+        if neuron.is_input() {
+            return Err(NeuralError::NeuronError(format!("Input neuron '{}' has no activation", id)));
         }
-        for (input_value, neuron) in input_values.iter().zip(self.inputs.iter()) {
-            let mut input_neuron = neuron.borrow_mut();
-            input_neuron.set_activation_value(*input_value);
+        if neuron.is_aggregate() {
+            // it is saved as its aggregate_outputs entry, which has no activation
+            return Err(NeuralError::NeuronError(format!(
+                "Aggregate output '{}' has no activation of its own",
+                id
+            )));
         }
-        for neuron in self.sorted_neurons.iter() {
-            let mut new_neuron = neuron.borrow_mut();
-            if !new_neuron.is_input() {
-                new_neuron.propagate();
+        Self::set_neuron_activation(id, &mut neuron, activation)
+    }
+
+    pub fn get_synapse_weight(&self, source: &str, target: &str) -> Result<Float, NeuralError> {
+        let neuron = self
+            .neuron_map
+            .get(target)
+            .ok_or_else(|| NeuralError::NetworkError(format!("Could not find neuron with id '{}'", target)))?;
+        neuron.borrow().get_synapses_map().get(source).copied().ok_or_else(|| {
+            NeuralError::NeuronError(format!("Neuron '{}' has no synapse from '{}'", target, source))
+        })
+    }
+
+    // input neurons, which can differ from the values of a line with input indices or categorical inputs
+    pub fn get_input_count(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn get_output_count(&self) -> usize {
+        self.outputs.len()
+    }
+
+    // passes the errors back through the whole network without touching the weights
+    fn backpropagate_errors(&self, errors: &mut [Float]) {
+        for (index, item) in self.sorted_neurons.iter().enumerate().rev() {
+            for group in self.softmax_groups.iter().filter(|group| group.last_index == index) {
+                group.backpropagate_error(errors);
             }
+            item.borrow().backpropagate_error(errors);
         }
-        Ok(())
     }
 
-    pub fn backpropagate(
-        &mut self,
-        expected_output_values: &Vec<f64>,
-        learning_rate: f64,
-    ) -> Result<(), String> {
+    fn input_errors(&self, mut errors: Vec<Float>) -> Vec<Float> {
+        self.backpropagate_errors(&mut errors);
+        self.inputs
+            .iter()
+            .map(|input| errors[input.borrow().get_index()])
+            .collect()
+    }
+
+    pub fn jacobian(&self) -> Vec<Vec<Float>> {
+        let mut jacobian = Vec::with_capacity(self.outputs.len());
+        for (position, output) in self.outputs.iter().enumerate() {
+            let mut errors = vec![0.0; self.sorted_neurons.len()];
+            // of the denormalized output
+            errors[output.borrow().get_index()] = self.output_std(position);
+            jacobian.push(self.input_errors(errors));
+        }
+        jacobian
+    }
+
+    // d(output)/d(activation) of every neuron for a unit error on the given output only, nothing is
+    // updated; the gradients of the loss are these weighted by each output's loss derivative
+    pub fn output_gradients(&self, output_index: usize) -> Result<HashMap<String, Float>, String> {
+        let output = self.outputs.get(output_index).ok_or_else(|| {
+            format!("Output index {} out of range, the network has {} outputs", output_index, self.outputs.len())
+        })?;
+        let mut errors = vec![0.0; self.sorted_neurons.len()];
+        errors[output.borrow().get_index()] = self.output_std(output_index);
+        self.backpropagate_errors(&mut errors);
+        Ok(self
+            .sorted_neurons
+            .iter()
+            .map(|neuron| {
+                let neuron = neuron.borrow();
+                (neuron.get_id().to_string(), errors[neuron.get_index()])
+            })
+            .collect())
+    }
+
+    pub fn input_gradients(&self, expected_output_values: &[Float]) -> Result<Vec<Float>, String> {
         if expected_output_values.len() != self.outputs.len() {
             return Err(format!(
                 "Output sizes do not match. {} vs {}",
@@ -197,62 +2199,351 @@ impl NeuralNetwork {
                 self.outputs.len()
             ));
         }
-        let output_results: Vec<f64> = self
-            .outputs
-            .iter()
-            .map(|x| x.borrow().get_activation_value())
-            .collect();
-        let total_error: f64 = self
-            .loss_function
-            .get_error(&output_results, &expected_output_values);
-        println!("[Error: {}]", total_error);
-        let mut error_map: HashMap<String, f64> = HashMap::new();
-
-        for (out_neuron, expected) in self.outputs.iter().zip(expected_output_values.iter()) {
-            let neuron = out_neuron.borrow_mut();
-            let error = self
-                .loss_function
-                .get_derivative(neuron.get_activation_value(), *expected);
-            error_map.insert(neuron.get_id().to_string(), error);
-        }
-        for item in self.sorted_neurons.iter().rev() {
+        let expected_output_values = self.normalize_expected(expected_output_values);
+        let mut errors = vec![0.0; self.sorted_neurons.len()];
+        for (out_neuron, error) in self.outputs.iter().zip(self.loss_derivatives(&expected_output_values)) {
+            errors[out_neuron.borrow().get_index()] += error;
+        }
+        Ok(self.input_errors(errors))
+    }
+
+    pub fn normalize_weights(&mut self) -> Result<Vec<(String, Float)>, NeuralError> {
+        let output_ids: Vec<String> = self.outputs.iter().map(|x| x.borrow().get_id().to_string()).collect();
+        // the softmax isn't scale-invariant, so its members keep their scale and so do the
+        // neurons feeding them, leaving every weight into a group untouched
+        let mut softmax_ids: HashSet<String> = HashSet::new();
+        for member in self.softmax_groups.iter().flat_map(|group| group.neurons.iter()) {
+            let member = member.borrow();
+            softmax_ids.insert(member.get_id().to_string());
+            softmax_ids.extend(member.get_synapses_map().into_keys());
+        }
+        let mut scales: HashMap<String, Float> = HashMap::new();
+        for item in self.sorted_neurons.iter() {
+            let neuron = item.borrow();
+            if neuron.is_input() || output_ids.iter().any(|id| id == neuron.get_id()) || softmax_ids.contains(neuron.get_id()) {
+                continue;
+            }
+            // the scale is moved into the downstream weights, which only preserves the function
+            // if the activation commutes with it
+            if neuron.get_aggregation() == Aggregation::Product {
+                return Err(NeuralError::NetworkError(format!(
+                    "Can't normalize the weights of neuron '{}', scaling the weights of a product changes it by more than the scale",
+                    neuron.get_id()
+                )));
+            }
+            if !neuron.is_positive_homogeneous() {
+                return Err(NeuralError::NetworkError(format!(
+                    "Can't normalize the weights of neuron '{}' with activation '{}', only Identity, Linear, ReLU, LeakyReLU and Maxout are supported",
+                    neuron.get_id(),
+                    neuron.get_activation_name()
+                )));
+            }
+            let norm = neuron.get_synapses_map().values().map(|w| w * w).sum::<Float>().sqrt();
+            if norm > 0.0 {
+                scales.insert(neuron.get_id().to_string(), norm);
+            }
+        }
+        for item in self.sorted_neurons.iter() {
+            let neuron = item.borrow();
+            let scale = scales.get(neuron.get_id()).copied().unwrap_or(1.0);
+            if neuron.is_aggregate() {
+                if let Some(lneuron_id) = neuron.get_synapses_map().keys().find(|id| scales.contains_key(*id)) {
+                    return Err(NeuralError::NetworkError(format!(
+                        "Can't normalize the weights of '{}', the aggregate output '{}' has fixed weights",
+                        lneuron_id,
+                        neuron.get_id()
+                    )));
+                }
+            }
+            for lneuron_id in neuron.get_residual_synapses() {
+                if scale != 1.0 || scales.get(&lneuron_id).is_some_and(|&lscale| lscale != 1.0) {
+                    return Err(NeuralError::NetworkError(format!(
+                        "Can't normalize the weights around the residual synapse from '{}' to '{}', its weight is fixed",
+                        lneuron_id,
+                        neuron.get_id()
+                    )));
+                }
+            }
+        }
+        for item in self.sorted_neurons.iter() {
             let mut neuron = item.borrow_mut();
-            neuron.backpropagate(&mut error_map, learning_rate);
+            let scale = scales.get(neuron.get_id()).copied().unwrap_or(1.0);
+            for (lneuron_id, weight) in neuron.get_synapses_map() {
+                let lscale = scales.get(&lneuron_id).copied().unwrap_or(1.0);
+                neuron.set_synapse_weight(&lneuron_id, weight * lscale / scale)?;
+            }
+            let bias = neuron.get_bias();
+            neuron.set_bias(bias / scale);
         }
-        Ok(())
+        let mut scales: Vec<(String, Float)> = scales.into_iter().collect();
+        scales.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(scales)
     }
 
     pub fn print_as_json(self) -> String {
+        serde_json::to_string_pretty(&self.to_config()).expect("Could not serialize the network")
+    }
+
+    // single line without indentation, much smaller for large networks
+    pub fn print_as_json_compact(self) -> String {
+        serde_json::to_string(&self.to_config()).expect("Could not serialize the network")
+    }
+
+    pub fn snapshot(&self) -> InferenceModel {
+        InferenceModel::from_network(self)
+    }
+
+    // a copy of the configuration that can be sent to other threads and loaded there
+    pub fn config_snapshot(&self) -> ConfigSnapshot {
+        ConfigSnapshot(self.to_config())
+    }
+
+    pub fn from_config_snapshot(snapshot: &ConfigSnapshot) -> Result<Self, NeuralError> {
+        Self::from_config(snapshot.0.clone(), &LoadOptions::default())
+    }
+
+    // true when every synapse comes from a shallower neuron, so no state is kept between propagations
+    pub fn is_feed_forward(&self) -> bool {
+        self.sorted_neurons.iter().all(|neuron| !neuron.borrow().has_recurrent_synapses())
+    }
+
+    fn to_config(&self) -> ConfigJson {
+        let input_names: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|neuron| neuron.borrow().get_id().to_string())
+            .collect();
+        let inputs = match &self.input_groups {
+            Some(input_groups) => {
+                let mut input_names = input_names.into_iter();
+                InputDefs::Grouped(
+                    input_groups
+                        .iter()
+                        .map(|(name, size)| InputGroupDefs {
+                            name: name.clone(),
+                            inputs: input_names.by_ref().take(*size).collect(),
+                        })
+                        .collect(),
+                )
+            }
+            None => InputDefs::Flat(input_names),
+        };
         let mut final_object = ConfigJson {
-            inputs: vec![],
+            include: vec![],
+            inputs,
+            input_indices: self.input_indices.clone(),
+            categorical_inputs: self.categorical_inputs(),
+            softmax_groups: self
+                .softmax_groups
+                .iter()
+                .map(|group| {
+                    let ids = group.neurons.iter().map(|neuron| neuron.borrow().get_id().to_string()).collect();
+                    (group.name.clone(), ids)
+                })
+                .collect(),
+            input_bounds: self.input_bounds(),
             outputs: vec![],
+            aggregate_outputs: self.aggregate_outputs.clone(),
+            output_normalization: self.output_normalization(),
+            loss: self.loss_terms(),
             neurons: HashMap::new(),
         };
-        for neuron in self.inputs.iter() {
-            let neuron_name = neuron.borrow().get_id().to_string();
-            final_object.inputs.push(neuron_name);
-        }
         for neuron in self.outputs.iter() {
             let neuron_name = neuron.borrow().get_id().to_string();
             final_object.outputs.push(neuron_name);
         }
         for neuron in self.sorted_neurons.iter() {
             let neuron = neuron.borrow();
-            if neuron.is_input() {
+            if neuron.is_input() || neuron.is_aggregate() {
                 continue;
             }
             let neuron_id = neuron.get_id().to_string();
             let activation = neuron.get_activation_name();
+            let activation_params = neuron.get_activation_params();
             let bias = neuron.get_bias();
-            let synapses: HashMap<String, f64> = neuron.get_synapses_map();
+            let residual_synapses = neuron.get_residual_synapses();
+            let synapses: HashMap<String, SynapseDefs> = neuron
+                .get_synapses_map()
+                .into_iter()
+                .map(|(id, weight)| match residual_synapses.contains(&id) {
+                    true => (id, SynapseDefs::Detailed(DetailedSynapseDefs { weight: None, residual: true })),
+                    false => (id, SynapseDefs::Weight(Some(weight))),
+                })
+                .collect();
+            let source_ids = neuron.get_synapse_ids();
+            // every synapse has a state after an RProp update, a synapse connected since then drops it
+            let rprop = match neuron.get_rprop_states() {
+                [bias, synapses @ ..] if synapses.len() == source_ids.len() => Some(RPropDefs {
+                    bias: bias.clone(),
+                    synapses: source_ids.into_iter().zip(synapses.iter().cloned()).collect(),
+                }),
+                _ => None,
+            };
             let neurondefs = NeuronDefs {
                 activation,
+                activation_params,
                 bias,
+                aggregation: neuron.get_aggregation(),
                 synapses,
+                layer: neuron.get_layer().map(str::to_string),
+                gate: neuron.get_gate_id(),
+                depth: Some(neuron.get_depth()),
+                rprop,
             };
             final_object.neurons.insert(neuron_id, neurondefs);
         }
-        serde_json::to_string_pretty(&final_object).expect("Could not serialize the network")
+        final_object
+    }
+
+    fn output_normalization(&self) -> HashMap<String, OutputNormalizationDefs> {
+        let mut output_normalization = HashMap::new();
+        for (neuron, &(mean, std)) in self.outputs.iter().zip(self.output_normalization.iter().flatten()) {
+            if mean != 0.0 || std != 1.0 {
+                output_normalization.insert(neuron.borrow().get_id().to_string(), OutputNormalizationDefs { mean, std });
+            }
+        }
+        output_normalization
+    }
+
+    fn input_bounds(&self) -> HashMap<String, InputBoundsDefs> {
+        let mut input_bounds = HashMap::new();
+        for (neuron, &(min, max)) in self.inputs.iter().zip(self.input_bounds.iter().flatten()) {
+            if min.is_finite() || max.is_finite() {
+                let defs = InputBoundsDefs {
+                    min: min.is_finite().then_some(min),
+                    max: max.is_finite().then_some(max),
+                };
+                input_bounds.insert(neuron.borrow().get_id().to_string(), defs);
+            }
+        }
+        input_bounds
+    }
+
+    fn categorical_inputs(&self) -> HashMap<String, Vec<String>> {
+        let mut categorical_inputs = HashMap::new();
+        for column in self.input_columns.iter().flatten() {
+            if let InputColumn::Categorical(name, positions) = column {
+                let ids = positions
+                    .iter()
+                    .map(|&position| self.inputs[position].borrow().get_id().to_string())
+                    .collect();
+                categorical_inputs.insert(name.clone(), ids);
+            }
+        }
+        categorical_inputs
+    }
+
+    pub fn print_description(&self) {
+        let synapse_count: usize = self
+            .sorted_neurons
+            .iter()
+            .map(|neuron| neuron.borrow().get_synapse_count())
+            .sum();
+        println!("Inputs: {}", self.inputs.len());
+        if let Some(input_groups) = &self.input_groups {
+            let input_groups: Vec<String> = input_groups
+                .iter()
+                .map(|(name, size)| format!("{}({})", name, size))
+                .collect();
+            println!("Input groups: {}", input_groups.join(" "));
+        }
+        let categorical_inputs: Vec<String> = self
+            .input_columns
+            .iter()
+            .flatten()
+            .filter_map(|column| match column {
+                InputColumn::Categorical(name, positions) => Some(format!("{}({})", name, positions.len())),
+                InputColumn::Value(_) => None,
+            })
+            .collect();
+        if !categorical_inputs.is_empty() {
+            println!("Categorical inputs: {}", categorical_inputs.join(" "));
+        }
+        println!("Outputs: {}", self.outputs.len());
+        if !self.aggregate_outputs.is_empty() {
+            println!("Aggregate outputs: {}", self.aggregate_outputs.len());
+        }
+        if !self.softmax_groups.is_empty() {
+            let groups: Vec<String> = self
+                .softmax_groups
+                .iter()
+                .map(|group| format!("{}({})", group.name, group.neurons.len()))
+                .collect();
+            println!("Softmax groups: {}", groups.join(" "));
+        }
+        println!("Neurons: {}", self.neuron_map.len() - self.inputs.len() - self.aggregate_outputs.len());
+        println!("Synapses: {}", synapse_count);
+    }
+
+    // (total, trainable) parameters, every synapse plus the bias of each non-input neuron,
+    // the weights tied within a layer counting once for the whole layer
+    pub fn parameter_counts(&self) -> (usize, usize) {
+        let mut total = 0;
+        let mut fixed = 0;
+        let mut counted_layers: HashSet<&str> = HashSet::new();
+        for (layer, neurons) in self.layers.iter() {
+            let neuron = neurons[0].borrow();
+            total += neuron.get_synapses_map().len();
+            fixed += neuron.get_residual_synapses().len();
+            counted_layers.insert(layer.as_str());
+        }
+        for neuron in self.sorted_neurons.iter() {
+            let neuron = neuron.borrow();
+            if neuron.is_input() || neuron.is_aggregate() {
+                continue;
+            }
+            total += 1;
+            if neuron.get_layer().is_none_or(|layer| !counted_layers.contains(layer)) {
+                total += neuron.get_synapses_map().len();
+                fixed += neuron.get_residual_synapses().len();
+            }
+        }
+        (total, total - fixed)
+    }
+
+    // read only access in the order neurons are propagated in: by depth, then by id
+    pub fn neurons_sorted(&self) -> impl Iterator<Item = Ref<'_, Neuron>> {
+        self.sorted_neurons.iter().map(|neuron| neuron.borrow())
+    }
+
+    pub fn print_depths(&self) {
+        for neuron in self.neurons_sorted() {
+            println!("{}: {}", neuron.get_id(), neuron.get_depth());
+        }
+    }
+
+    pub fn print_parameter_counts(&self) {
+        let (total, trainable) = self.parameter_counts();
+        println!("Parameters: {}", total);
+        println!("Trainable parameters: {}", trainable);
+    }
+
+    pub fn print_memory_report(&self) {
+        use std::mem::size_of;
+
+        let rc_size = size_of::<Rc<RefCell<Neuron>>>();
+        // Rc allocations hold the strong and weak counters next to the value
+        let rc_box_size = 2 * size_of::<usize>() + size_of::<RefCell<Neuron>>();
+
+        let mut neuron_map_bytes = self.neuron_map.capacity() * (size_of::<(String, Rc<RefCell<Neuron>>)>() + 1);
+        let mut neuron_bytes = 0;
+        let mut synapse_bytes = 0;
+        for (id, neuron) in self.neuron_map.iter() {
+            let neuron = neuron.borrow();
+            neuron_map_bytes += id.capacity();
+            neuron_bytes += rc_box_size + neuron.get_id_memory();
+            synapse_bytes += neuron.get_synapses_memory();
+        }
+        let sorted_neurons_bytes = self.sorted_neurons.capacity() * rc_size;
+        let io_bytes = (self.inputs.capacity() + self.outputs.capacity()) * rc_size;
+        let total = neuron_map_bytes + neuron_bytes + synapse_bytes + sorted_neurons_bytes + io_bytes;
+
+        println!("neuron_map: {} bytes", neuron_map_bytes);
+        println!("neurons: {} bytes ({} x Rc<RefCell<Neuron>>)", neuron_bytes, self.neuron_map.len());
+        println!("synapses: {} bytes", synapse_bytes);
+        println!("sorted_neurons: {} bytes", sorted_neurons_bytes);
+        println!("inputs/outputs: {} bytes", io_bytes);
+        println!("total: {} bytes", total);
     }
 
     #[allow(dead_code)]
@@ -262,12 +2553,487 @@ impl NeuralNetwork {
         for item in self.sorted_neurons.iter() {
             let neuron = item.borrow_mut();
             if neuron.get_depth() != line_no {
-                println!("");
+                println!();
                 line_no = neuron.get_depth();
                 print!("{}:  ", line_no);
             }
             print!("{}  ", neuron.get_id());
         }
-        println!("");
+        println!();
+    }
+}
+
+impl FromStr for NeuralNetwork {
+    type Err = NeuralError;
+
+    fn from_str(json: &str) -> Result<Self, Self::Err> {
+        Self::from_bytes(json.as_bytes())
+    }
+}
+
+type GradientUpdate = (Rc<RefCell<Neuron>>, Float, Vec<Float>);
+
+// Replaces the weight gradients of every neuron in a layer by their mean over the layer's neurons
+// in `updates`. Each tied neuron then takes the same step, RProp's sign-based one included, which
+// averaging the weights after separate steps would not give.
+fn average_tied_gradients(updates: &mut [GradientUpdate]) {
+    let mut layer_sums: HashMap<String, (usize, HashMap<String, Float>)> = HashMap::new();
+    for (neuron, _, weight_gradients) in updates.iter() {
+        let neuron = neuron.borrow();
+        if let Some(layer) = neuron.get_layer() {
+            let (count, sums) = layer_sums.entry(layer.to_string()).or_default();
+            *count += 1;
+            for (source, gradient) in neuron.get_synapse_ids().into_iter().zip(weight_gradients) {
+                *sums.entry(source).or_insert(0.0) += gradient;
+            }
+        }
+    }
+    for (neuron, _, weight_gradients) in updates.iter_mut() {
+        let neuron = neuron.borrow();
+        if let Some((count, sums)) = neuron.get_layer().and_then(|layer| layer_sums.get(layer)) {
+            for (source, gradient) in neuron.get_synapse_ids().iter().zip(weight_gradients.iter_mut()) {
+                *gradient = sums[source] / *count as Float;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(json: &str) -> NeuralNetwork {
+        json.parse().expect("invalid test config")
+    }
+
+    fn depths(nn: &NeuralNetwork) -> HashMap<String, u32> {
+        nn.neurons_sorted().map(|neuron| (neuron.get_id().to_string(), neuron.get_depth())).collect()
+    }
+
+    fn assert_close(actual: Float, expected: Float) {
+        assert!((actual - expected).abs() <= 1e-5 * expected.abs().max(1.0), "{} vs {}", actual, expected);
+    }
+
+    fn weight(nn: &NeuralNetwork, id: &str, source: &str) -> Float {
+        nn.neuron_map[id].borrow().get_synapses_map()[source]
+    }
+
+    fn bias(nn: &NeuralNetwork, id: &str) -> Float {
+        nn.neuron_map[id].borrow().get_bias()
+    }
+
+    #[test]
+    fn stale_cached_depths_are_recalculated() {
+        // O is really at depth 3, the cached depth 1 would make the synapse from H recurrent
+        let config = |depths: [u32; 3]| {
+            format!(
+                r#"{{"inputs": ["X"], "outputs": ["O"], "neurons": {{
+                    "G": {{"activation": "TanH", "synapses": {{"X": 0.5}}, "depth": {}}},
+                    "H": {{"activation": "TanH", "synapses": {{"G": 0.7}}, "depth": {}}},
+                    "O": {{"synapses": {{"X": 0.3, "H": 1.1}}, "depth": {}}}
+                }}}}"#,
+                depths[0], depths[1], depths[2]
+            )
+        };
+        let mut fresh = network(&config([1, 2, 3]));
+        let mut stale = network(&config([1, 2, 1]));
+        assert_eq!(depths(&stale)["O"], 3);
+        fresh.propagate(&[0.8]).unwrap();
+        stale.propagate(&[0.8]).unwrap();
+        assert_eq!(stale.outputs(), fresh.outputs());
+    }
+
+    #[test]
+    fn cached_depths_of_cycles_are_kept() {
+        let nn = network(
+            r#"{"inputs": ["X"], "outputs": ["B"], "neurons": {
+                "A": {"synapses": {"X": 1.0, "B": 0.5}, "depth": 1},
+                "B": {"synapses": {"A": 1.0, "B": 0.25}, "depth": 2}
+            }}"#,
+        );
+        let cached = HashMap::from([("A".to_string(), 1), ("B".to_string(), 2)]);
+        assert!(nn.check_cached_depths(&cached).is_ok());
+        assert_eq!(depths(&nn)["B"], 2);
+    }
+
+    #[test]
+    fn cached_depths_putting_a_cycle_at_one_depth_are_recalculated() {
+        let nn = network(
+            r#"{"inputs": ["X"], "outputs": ["B"], "neurons": {
+                "A": {"synapses": {"X": 1.0, "B": 0.5}, "depth": 1},
+                "B": {"synapses": {"X": 1.0, "A": 0.5}, "depth": 1}
+            }}"#,
+        );
+        let cached = HashMap::from([("A".to_string(), 1), ("B".to_string(), 1)]);
+        assert!(nn.check_cached_depths(&cached).is_err());
+        assert_ne!(depths(&nn)["A"], depths(&nn)["B"]);
+    }
+
+    #[test]
+    fn set_activation_takes_params_and_rejects_neurons_without_activation() {
+        let mut nn = network(
+            r#"{"inputs": ["X"], "outputs": ["H", "S"], "neurons": {"H": {"synapses": {"X": 1.0}}},
+                "aggregate_outputs": {"S": {"aggregation": "sum", "neurons": ["H"]}}}"#,
+        );
+        nn.set_activation("H", "binary", &[0.5]).unwrap();
+        nn.propagate(&[0.4]).unwrap();
+        assert_eq!(nn.outputs()[0], 0.0);
+        nn.propagate(&[0.6]).unwrap();
+        assert_eq!(nn.outputs()[0], 1.0);
+        assert!(nn.set_activation("X", "ReLU", &[]).is_err());
+        assert!(nn.set_activation("S", "ReLU", &[]).is_err());
+        assert!(nn.set_activation("H", "ReLU", &[1.0]).is_err());
+        assert!(nn.set_activation("missing", "ReLU", &[]).is_err());
+    }
+
+    #[test]
+    fn normalize_weights_keeps_softmax_groups() {
+        let mut nn = network(
+            r#"{"inputs": ["X"], "outputs": ["O"], "softmax_groups": {"G": ["A", "B"]}, "neurons": {
+                "H": {"activation": "ReLU", "synapses": {"X": 3.0}},
+                "A": {"activation": "ReLU", "synapses": {"H": 2.0}, "bias": 0.5},
+                "B": {"activation": "ReLU", "synapses": {"X": 4.0}},
+                "O": {"synapses": {"A": 1.5, "B": -0.5}}
+            }}"#,
+        );
+        nn.propagate(&[0.7]).unwrap();
+        let before = nn.outputs();
+        assert!(nn.normalize_weights().unwrap().is_empty());
+        nn.propagate(&[0.7]).unwrap();
+        assert_eq!(nn.outputs(), before);
+    }
+
+    #[test]
+    fn loss_epsilon_reaches_cross_entropy_terms_of_the_config() {
+        let mut nn = network(
+            r#"{"inputs": ["X"], "outputs": ["O"], "neurons": {"O": {"synapses": {"X": 0.0}}},
+                "loss": [{"function": "cross-entropy"}]}"#,
+        );
+        let derivative = |epsilon| LossFunction::LossCrossEntropy(epsilon).get_derivative(0.0, 1.0);
+        nn.propagate(&[1.0]).unwrap();
+        assert_eq!(nn.loss_derivatives(&[1.0]), vec![derivative(DEFAULT_CROSS_ENTROPY_EPSILON)]);
+        nn.set_loss_epsilon(1e-3);
+        assert_eq!(nn.loss_derivatives(&[1.0]), vec![derivative(1e-3)]);
+    }
+
+    #[test]
+    fn rprop_state_survives_saving_and_reloading() {
+        let mut nn = network(
+            r#"{"inputs": ["X", "Y"], "outputs": ["O"], "neurons": {
+                "H": {"activation": "TanH", "synapses": {"X": 0.4, "Y": -0.3}, "bias": 0.1},
+                "O": {"synapses": {"H": 0.8, "X": 0.2}}
+            }}"#,
+        );
+        nn.set_optimizer(Optimizer::RProp);
+        let samples = [([0.5, -1.0], [0.3]), ([-0.2, 0.7], [-0.4])];
+        let train = |nn: &mut NeuralNetwork, steps: usize| {
+            for (inputs, expected) in samples.iter().cycle().take(steps) {
+                nn.propagate(inputs).unwrap();
+                nn.backpropagate(expected, 0.05).unwrap();
+            }
+        };
+        train(&mut nn, 5);
+        let mut reloaded = NeuralNetwork::from_config_snapshot(&nn.config_snapshot()).unwrap();
+        reloaded.set_optimizer(Optimizer::RProp);
+        // a fresh state would start every step over at the learning rate
+        train(&mut nn, 6);
+        train(&mut reloaded, 6);
+        let json = |nn: &NeuralNetwork| serde_json::to_value(nn.to_config()).unwrap();
+        assert!(nn.to_config().neurons["H"].rprop.is_some());
+        assert_eq!(json(&reloaded), json(&nn));
+    }
+
+    #[test]
+    fn tied_weights_take_one_rprop_step_with_the_mean_gradient() {
+        // O = A - 3B, so the gradients of the tied weights have opposite signs and their mean has
+        // the sign of B's
+        let config = r#"{"inputs": ["X"], "outputs": ["O"], "neurons": {
+            "A": {"layer": "hidden", "synapses": {"X": 0.5}},
+            "B": {"layer": "hidden", "synapses": {"X": 0.5}},
+            "O": {"synapses": {"A": 1.0, "B": -3.0}}
+        }}"#;
+        let mut stepped = network(config);
+        let mut applied = network(config);
+        for nn in [&mut stepped, &mut applied] {
+            nn.set_optimizer(Optimizer::RProp);
+            nn.propagate(&[1.0]).unwrap();
+        }
+        stepped.backpropagate(&[2.0], 0.1).unwrap();
+        let (_, gradients) = applied.compute_gradients(&[2.0]).unwrap();
+        applied.apply_gradients(&gradients, 0.1).unwrap();
+        for nn in [&stepped, &applied] {
+            assert_close(weight(nn, "A", "X"), 0.4);
+            assert_close(weight(nn, "B", "X"), 0.4);
+        }
+    }
+
+    #[test]
+    fn backpropagation_matches_a_hand_computed_gradient() {
+        // X -> H, G (TanH, the same depth) -> O (Identity), squared loss and a learning rate of 1,
+        // so every parameter moves by exactly minus its gradient
+        let mut nn = network(
+            r#"{"inputs": ["X"], "outputs": ["O"], "neurons": {
+                "H": {"activation": "TanH", "synapses": {"X": 0.8}, "bias": 0.1},
+                "G": {"activation": "TanH", "synapses": {"X": -0.6}, "bias": 0.2},
+                "O": {"synapses": {"H": 1.5, "G": -0.7}, "bias": 0.05}
+            }}"#,
+        );
+        let (x, target) = (0.5, 0.3);
+        let h = Float::tanh(0.8 * x + 0.1);
+        let g = Float::tanh(-0.6 * x + 0.2);
+        let o = 1.5 * h - 0.7 * g + 0.05;
+        let d_o = 2.0 * (o - target);
+        let d_h = d_o * 1.5 * (1.0 - h * h);
+        let d_g = d_o * -0.7 * (1.0 - g * g);
+        nn.propagate(&[x]).unwrap();
+        nn.backpropagate(&[target], 1.0).unwrap();
+        assert_close(weight(&nn, "O", "H"), 1.5 - d_o * h);
+        assert_close(weight(&nn, "O", "G"), -0.7 - d_o * g);
+        assert_close(bias(&nn, "O"), 0.05 - d_o);
+        assert_close(weight(&nn, "H", "X"), 0.8 - d_h * x);
+        assert_close(bias(&nn, "H"), 0.1 - d_h);
+        assert_close(weight(&nn, "G", "X"), -0.6 - d_g * x);
+        assert_close(bias(&nn, "G"), 0.2 - d_g);
+    }
+
+    #[test]
+    fn single_and_double_precision_agree() {
+        // the same values are expected from both builds, they were computed in double precision
+        let mut nn = network(
+            r#"{"inputs": ["X", "Y"], "outputs": ["O"], "neurons": {
+                "H": {"activation": "TanH", "synapses": {"X": 0.8, "Y": -0.4}, "bias": 0.1},
+                "G": {"activation": "SoftStep", "synapses": {"X": -0.6, "Y": 0.9}, "bias": 0.2},
+                "O": {"synapses": {"H": 1.5, "G": -0.7}, "bias": 0.05}
+            }}"#,
+        );
+        let samples = [([0.0, 0.0], [0.0]), ([0.0, 1.0], [1.0]), ([1.0, 0.0], [1.0]), ([1.0, 1.0], [0.0])];
+        for (inputs, expected) in samples.iter().cycle().take(400) {
+            nn.propagate(inputs).unwrap();
+            nn.backpropagate(expected, 0.1).unwrap();
+        }
+        let mut outputs = vec![];
+        for (inputs, _) in samples.iter() {
+            nn.propagate(inputs).unwrap();
+            outputs.push(nn.outputs()[0]);
+        }
+        let reference = [0.1494673, 0.39697, 0.4482176, 0.4135769];
+        for (output, reference) in outputs.iter().zip(reference) {
+            assert!((output - reference).abs() < 1e-4, "{} vs {}", output, reference);
+        }
+    }
+
+    #[test]
+    fn a_neuron_feeding_targets_at_different_depths_collects_both_errors() {
+        // A feeds B at depth 2 and O at depth 3, its gradient is the sum over both paths
+        let mut nn = network(
+            r#"{"inputs": ["X"], "outputs": ["O"], "neurons": {
+                "A": {"synapses": {"X": 0.6}},
+                "B": {"synapses": {"A": -1.2}},
+                "O": {"synapses": {"B": 0.7, "A": 0.9}}
+            }}"#,
+        );
+        assert_eq!(depths(&nn)["A"], 1);
+        assert_eq!(depths(&nn)["O"], 3);
+        let (x, target) = (2.0, 0.5);
+        let a = 0.6 * x;
+        let o = 0.7 * (-1.2 * a) + 0.9 * a;
+        let d_a = 2.0 * (o - target) * (0.7 * -1.2 + 0.9);
+        nn.propagate(&[x]).unwrap();
+        nn.backpropagate(&[target], 1.0).unwrap();
+        assert_close(weight(&nn, "A", "X"), 0.6 - d_a * x);
+        assert_close(bias(&nn, "A"), -d_a);
+    }
+
+    #[test]
+    fn residual_weights_stay_exactly_one() {
+        let mut nn = network(
+            r#"{"inputs": ["X"], "outputs": ["O"], "neurons": {
+                "H": {"activation": "TanH", "synapses": {"X": 0.4}},
+                "O": {"synapses": {"H": 0.8, "X": {"residual": true}}}
+            }}"#,
+        );
+        for _ in 0..20 {
+            nn.propagate(&[0.7]).unwrap();
+            nn.backpropagate(&[-0.5], 0.1).unwrap();
+        }
+        assert_eq!(weight(&nn, "O", "X"), 1.0);
+        assert_ne!(weight(&nn, "O", "H"), 0.8);
+    }
+
+    #[test]
+    fn configs_feeding_into_an_input_are_rejected() {
+        let config = r#"{"inputs": ["X", "Y"], "outputs": ["O"], "neurons": {
+            "Y": {"synapses": {"X": 0.5}},
+            "O": {"synapses": {"Y": 1.0}}
+        }}"#;
+        let error = config.parse::<NeuralNetwork>().unwrap_err();
+        assert!(error.to_string().contains("'Y' is an input"), "{}", error);
+    }
+
+    #[test]
+    fn a_masked_output_gives_the_gradient_of_the_network_without_it() {
+        let config = |outputs: &str, extra_neurons: &str| {
+            format!(
+                r#"{{"inputs": ["X"], "outputs": [{}], "neurons": {{
+                    "H": {{"activation": "TanH", "synapses": {{"X": 0.6}}, "bias": 0.1}},
+                    "O": {{"synapses": {{"H": 1.3}}}}{}
+                }}}}"#,
+                outputs, extra_neurons
+            )
+        };
+        let mut masked = network(&config(r#""O", "P""#, r#", "P": {"synapses": {"H": -0.8}}"#));
+        let mut removed = network(&config(r#""O""#, ""));
+        masked.propagate(&[0.9]).unwrap();
+        removed.propagate(&[0.9]).unwrap();
+        let (masked_loss, masked_gradients) = masked.compute_gradients(&[0.2, Float::NAN]).unwrap();
+        let (removed_loss, removed_gradients) = removed.compute_gradients(&[0.2]).unwrap();
+        assert_eq!(masked_loss, removed_loss);
+        let gradients: HashMap<&String, (Float, &HashMap<String, Float>)> =
+            masked_gradients.iter().map(|(id, bias, synapses)| (id, (bias, synapses))).collect();
+        for (id, bias, synapses) in removed_gradients.iter() {
+            assert_eq!(gradients[id], (bias, synapses));
+        }
+        // nothing flows from the masked output
+        assert_eq!(gradients[&"P".to_string()].0, 0.0);
+    }
+
+    #[test]
+    fn halving_the_loss_scale_and_doubling_the_learning_rate_gives_the_same_updates() {
+        let config = r#"{"inputs": ["X", "Y"], "outputs": ["O"], "neurons": {
+            "H": {"activation": "TanH", "synapses": {"X": 0.8, "Y": -0.4}, "bias": 0.1},
+            "O": {"synapses": {"H": 1.5, "X": 0.3}}
+        }}"#;
+        let (mut full, mut half) = (network(config), network(config));
+        half.set_loss_scale(0.5);
+        for (inputs, expected) in [([0.5, -1.0], [0.3]), ([-0.2, 0.7], [-0.4]), ([1.0, 1.0], [0.0])] {
+            full.propagate(&inputs).unwrap();
+            half.propagate(&inputs).unwrap();
+            // the reported loss is not scaled
+            assert_eq!(full.backpropagate(&expected, 0.2).unwrap(), half.backpropagate(&expected, 0.4).unwrap());
+        }
+        let json = |nn: &NeuralNetwork| serde_json::to_value(nn.to_config()).unwrap();
+        assert_eq!(json(&full), json(&half));
+    }
+
+    #[test]
+    fn output_normalization_round_trips() {
+        let mut nn = network(
+            r#"{"inputs": ["X"], "outputs": ["PRICE", "O"], "neurons": {
+                "PRICE": {"synapses": {"X": 0.5}, "bias": 0.25},
+                "O": {"synapses": {"X": -1.5}}
+            }, "output_normalization": {"PRICE": {"mean": 1000.0, "std": 200.0}}}"#,
+        );
+        nn.propagate(&[0.5]).unwrap();
+        // the neuron works in normalized space, the outputs are denormalized
+        assert_eq!(nn.neuron_map["PRICE"].borrow().get_activation_value(), 0.5);
+        let outputs = nn.outputs();
+        assert_eq!(outputs, vec![1100.0, -0.75]);
+        // normalizing the denormalized outputs gives the neuron values back, with no loss
+        assert_eq!(nn.normalize_expected(&outputs).to_vec(), vec![0.5, -0.75]);
+        assert_eq!(nn.get_error(&outputs).unwrap(), 0.0);
+        let mut reloaded = NeuralNetwork::from_config_snapshot(&nn.config_snapshot()).unwrap();
+        reloaded.propagate(&[0.5]).unwrap();
+        assert_eq!(reloaded.outputs(), outputs);
+    }
+
+    #[test]
+    fn composite_loss_sums_the_weighted_terms() {
+        let mut nn = network(
+            r#"{"inputs": ["X"], "outputs": ["A", "B", "C"], "neurons": {
+                "A": {"synapses": {"X": 1.0}},
+                "B": {"synapses": {"X": 2.0}},
+                "C": {"synapses": {"X": -1.0}}
+            }, "loss": [
+                {"function": "squared", "outputs": ["A", "B"]},
+                {"function": "absolute", "weight": 0.5, "outputs": ["B", "C"]}
+            ]}"#,
+        );
+        nn.propagate(&[0.5]).unwrap();
+        // outputs 0.5, 1.0 and -0.5
+        let expected = [0.0, 0.5, 0.5];
+        assert_close(nn.get_error(&expected).unwrap(), 0.25 + 0.25 + 0.5 * (0.5 + 1.0));
+        let derivatives = nn.loss_derivatives(&expected);
+        // B is in both terms and gets the sum of their derivatives
+        assert_close(derivatives[0], 2.0 * 0.5);
+        assert_close(derivatives[1], 2.0 * 0.5 + 0.5 * 1.0);
+        assert_close(derivatives[2], -0.5);
+    }
+
+    #[test]
+    fn label_smoothing_softens_the_labeled_targets_only() {
+        let config = r#"{"inputs": ["X"], "outputs": ["A", "B", "C", "D"], "neurons": {
+            "A": {"activation": "SoftStep", "synapses": {"X": 0.5}},
+            "B": {"activation": "SoftStep", "synapses": {"X": -0.5}},
+            "C": {"activation": "SoftStep", "synapses": {"X": 1.0}},
+            "D": {"activation": "SoftStep", "synapses": {"X": 2.0}}
+        }}"#;
+        let mut smoothed = network(config);
+        smoothed.set_label_smoothing(0.3);
+        let targets = smoothed.smooth_labels(&[0.0, 1.0, 0.0, Float::NAN]).to_vec();
+        // alpha is spread over the 3 labeled outputs, the unlabeled one stays masked
+        assert_close(targets[0], 0.1);
+        assert_close(targets[1], 0.8);
+        assert_close(targets[2], 0.1);
+        assert!(targets[3].is_nan());
+        assert_close(targets[..3].iter().sum(), 1.0);
+        // training on hard targets with smoothing is training on the smoothed targets
+        let mut plain = network(config);
+        smoothed.propagate(&[0.7]).unwrap();
+        plain.propagate(&[0.7]).unwrap();
+        let (_, smoothed_gradients) = smoothed.compute_gradients(&[0.0, 1.0, 0.0, Float::NAN]).unwrap();
+        let (_, plain_gradients) = plain.compute_gradients(&targets).unwrap();
+        let (_, hard_gradients) = plain.compute_gradients(&[0.0, 1.0, 0.0, Float::NAN]).unwrap();
+        let bias = |gradients: &Gradients, id: &str| gradients.iter().find(|(other, _, _)| *other == id).unwrap().1;
+        for id in ["A", "B", "C", "D"] {
+            assert_eq!(bias(&smoothed_gradients, id), bias(&plain_gradients, id), "{}", id);
+        }
+        assert_ne!(bias(&smoothed_gradients, "B"), bias(&hard_gradients, "B"));
+        assert_eq!(bias(&smoothed_gradients, "D"), 0.0);
+    }
+
+    #[test]
+    fn includes_with_sections_that_are_not_merged_are_rejected() {
+        let dir = std::env::temp_dir().join(format!("mmnn-include-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let load = |block: &str| {
+            std::fs::write(dir.join("block.json"), block).unwrap();
+            std::fs::write(
+                dir.join("main.json"),
+                r#"{"include": ["block.json"], "inputs": ["X"], "outputs": ["O"],
+                    "neurons": {"O": {"synapses": {"block.H": 1.0}}}}"#,
+            )
+            .unwrap();
+            NeuralNetwork::new(dir.join("main.json"))
+        };
+        let block = |extra: &str| {
+            format!(r#"{{"inputs": ["X"], "outputs": ["H"], "neurons": {{"H": {{"synapses": {{"X": 0.5}}}}}}{}}}"#, extra)
+        };
+        assert!(load(&block("")).is_ok());
+        for extra in [
+            r#", "input_indices": [0]"#,
+            r#", "input_bounds": {"X": {"min": 0.0}}"#,
+            r#", "output_normalization": {"H": {"std": 2.0}}"#,
+            r#", "loss": [{"function": "absolute"}]"#,
+        ] {
+            let error = load(&block(extra)).unwrap_err();
+            assert!(error.to_string().contains("only its neurons and softmax_groups"), "{}", error);
+        }
+        let error = load(r#"{"inputs": ["X"], "outputs": ["Z"], "neurons": {"H": {"synapses": {"X": 0.5}}}}"#).unwrap_err();
+        assert!(error.to_string().contains("Output 'Z' of include 'block'"), "{}", error);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tied_layer_weights_are_counted_once() {
+        let nn = network(
+            r#"{"inputs": ["X", "Y"], "outputs": ["O"], "neurons": {
+                "A": {"layer": "hidden", "synapses": {"X": 0.5, "Y": -0.3}},
+                "B": {"layer": "hidden", "synapses": {"X": 0.1, "Y": 0.2}},
+                "C": {"layer": "hidden", "synapses": {"X": 0.3, "Y": 0.4}},
+                "O": {"synapses": {"A": 1.0, "B": 1.0, "C": 1.0}}
+            }}"#,
+        );
+        // 2 shared weights and 3 biases for the layer, 3 weights and a bias for the output
+        assert_eq!(nn.parameter_counts(), (9, 9));
     }
 }
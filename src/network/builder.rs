@@ -0,0 +1,51 @@
+use super::{InputDefs, LoadOptions, NeuralNetwork};
+use crate::error::NeuralError;
+use crate::float::Float;
+use crate::neurons::ActivationFunction;
+
+#[derive(Debug, Default)]
+pub struct NeuralNetworkBuilder {
+    inputs: Vec<String>,
+    neurons: Vec<(String, ActivationFunction, Float)>,
+    synapses: Vec<(String, String, Float)>,
+    outputs: Vec<String>,
+}
+
+impl NeuralNetworkBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn input(mut self, id: &str) -> Self {
+        self.inputs.push(id.to_string());
+        self
+    }
+
+    pub fn neuron(mut self, id: &str, activation: ActivationFunction, bias: Float) -> Self {
+        self.neurons.push((id.to_string(), activation, bias));
+        self
+    }
+
+    pub fn connect(mut self, from: &str, to: &str, weight: Float) -> Self {
+        self.synapses.push((from.to_string(), to.to_string(), weight));
+        self
+    }
+
+    pub fn output(mut self, id: &str) -> Self {
+        self.outputs.push(id.to_string());
+        self
+    }
+
+    pub fn build(self) -> Result<NeuralNetwork, NeuralError> {
+        let mut nn = NeuralNetwork::empty();
+        nn.create_inputs(&InputDefs::Flat(self.inputs));
+        for (id, activation, bias) in self.neurons {
+            nn.create_neuron(&id, activation, bias)?;
+        }
+        for (from, to, weight) in &self.synapses {
+            nn.connect_neurons(from, to, *weight)?;
+        }
+        nn.finish(&self.outputs, &LoadOptions::default())?;
+        Ok(nn)
+    }
+}
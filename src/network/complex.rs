@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use num_complex::Complex;
+use serde::{Deserialize, Serialize};
+
+use crate::error::NeuralError;
+use crate::float::Float;
+
+pub type ComplexFloat = Complex<Float>;
+
+// The activations with a complex variant. Gradients are passed around as dL/dRe + i * dL/dIm,
+// which for the holomorphic weighted sum lets them flow back through the conjugated weights.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ComplexActivation {
+    // f(z) = z
+    Linear,
+    // ReLU of the real and of the imaginary part, f(z) = max(0, Re z) + i * max(0, Im z)
+    CReLU,
+}
+
+impl ComplexActivation {
+    pub fn from_name(name: &str) -> Result<Self, NeuralError> {
+        match name {
+            "Linear" | "Identity" => Ok(Self::Linear),
+            "CReLU" => Ok(Self::CReLU),
+            _ => Err(NeuralError::NeuronError(format!(
+                "Activation '{}' has no complex variant, expected Linear, Identity or CReLU",
+                name
+            ))),
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::Linear => "Linear",
+            Self::CReLU => "CReLU",
+        }
+    }
+
+    pub fn activate(&self, z: ComplexFloat) -> ComplexFloat {
+        match self {
+            Self::Linear => z,
+            Self::CReLU => Complex::new(z.re.max(0.0), z.im.max(0.0)),
+        }
+    }
+
+    // gradient with respect to z from the one with respect to f(z)
+    pub fn backpropagate(&self, z: ComplexFloat, gradient: ComplexFloat) -> ComplexFloat {
+        let pass = |value: Float, gradient: Float| if value > 0.0 { gradient } else { 0.0 };
+        match self {
+            Self::Linear => gradient,
+            Self::CReLU => Complex::new(pass(z.re, gradient.re), pass(z.im, gradient.im)),
+        }
+    }
+}
+
+fn default_activation() -> String {
+    "Linear".to_string()
+}
+
+// every complex value is written as [re, im]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct ComplexNeuronDefs {
+    #[serde(default = "default_activation")]
+    activation: String,
+    #[serde(default)]
+    bias: ComplexFloat,
+    #[serde(default)]
+    synapses: HashMap<String, ComplexFloat>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct ComplexConfigJson {
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    neurons: HashMap<String, ComplexNeuronDefs>,
+}
+
+#[derive(Debug)]
+struct ComplexNeuron {
+    activation: ComplexActivation,
+    bias: ComplexFloat,
+    // (value index of the source, weight)
+    synapses: Vec<(usize, ComplexFloat)>,
+    preactivation: ComplexFloat,
+}
+
+// A feed-forward network whose weights, biases and activations are complex numbers, trained on
+// the squared magnitude of the output errors. Values are indexed with the inputs first and the
+// neurons after them, sorted so every neuron comes after its sources.
+#[derive(Debug)]
+pub struct ComplexNetwork {
+    ids: Vec<String>,
+    input_count: usize,
+    neurons: Vec<ComplexNeuron>,
+    outputs: Vec<usize>,
+    values: Vec<ComplexFloat>,
+}
+
+impl ComplexNetwork {
+    fn from_config(cfg: ComplexConfigJson) -> Result<Self, NeuralError> {
+        let mut ids = cfg.inputs.clone();
+        if let Some(id) = cfg.inputs.iter().find(|id| cfg.neurons.contains_key(*id)) {
+            return Err(NeuralError::NetworkError(format!("Input '{}' can't have synapses", id)));
+        }
+        // a neuron is placed once all of its sources are, ties broken by id
+        let mut remaining: Vec<&String> = cfg.neurons.keys().collect();
+        remaining.sort();
+        while !remaining.is_empty() {
+            let ready: Vec<&String> = remaining
+                .iter()
+                .copied()
+                .filter(|id| cfg.neurons[*id].synapses.keys().all(|source| ids.contains(source)))
+                .collect();
+            if ready.is_empty() {
+                for id in remaining.iter() {
+                    if let Some(source) = cfg.neurons[*id]
+                        .synapses
+                        .keys()
+                        .find(|source| !ids.contains(source) && !cfg.neurons.contains_key(*source))
+                    {
+                        return Err(NeuralError::NetworkError(format!(
+                            "Neuron '{}' has a synapse from unknown neuron '{}'",
+                            id, source
+                        )));
+                    }
+                }
+                return Err(NeuralError::NetworkError(format!(
+                    "Neurons {:?} form a cycle, complex networks have to be feed-forward",
+                    remaining
+                )));
+            }
+            remaining.retain(|id| !ready.contains(id));
+            ids.extend(ready.into_iter().cloned());
+        }
+        let index: HashMap<&str, usize> = ids.iter().enumerate().map(|(index, id)| (id.as_str(), index)).collect();
+        let mut neurons = Vec::with_capacity(cfg.neurons.len());
+        for id in ids.iter().skip(cfg.inputs.len()) {
+            let defs = &cfg.neurons[id];
+            let activation = ComplexActivation::from_name(&defs.activation)
+                .map_err(|e| e.context(format!("while creating neuron '{}'", id)))?;
+            let mut synapses: Vec<(usize, ComplexFloat)> = defs
+                .synapses
+                .iter()
+                .map(|(source, weight)| (index[source.as_str()], *weight))
+                .collect();
+            synapses.sort_by_key(|(source, _)| *source);
+            neurons.push(ComplexNeuron {
+                activation,
+                bias: defs.bias,
+                synapses,
+                preactivation: Complex::new(0.0, 0.0),
+            });
+        }
+        let outputs = cfg
+            .outputs
+            .iter()
+            .map(|id| {
+                index
+                    .get(id.as_str())
+                    .copied()
+                    .filter(|&position| position >= cfg.inputs.len())
+                    .ok_or_else(|| NeuralError::NetworkError(format!("Output '{}' is not a neuron", id)))
+            })
+            .collect::<Result<Vec<usize>, NeuralError>>()?;
+        Ok(ComplexNetwork {
+            values: vec![Complex::new(0.0, 0.0); ids.len()],
+            ids,
+            input_count: cfg.inputs.len(),
+            neurons,
+            outputs,
+        })
+    }
+
+    pub fn propagate(&mut self, input_values: &[ComplexFloat]) -> Result<(), String> {
+        if input_values.len() != self.input_count {
+            return Err(format!(
+                "Input sizes do not match. {} vs {}",
+                input_values.len(),
+                self.input_count
+            ));
+        }
+        self.values[..self.input_count].copy_from_slice(input_values);
+        for (offset, neuron) in self.neurons.iter_mut().enumerate() {
+            let preactivation = neuron
+                .synapses
+                .iter()
+                .fold(neuron.bias, |sum, (source, weight)| sum + weight * self.values[*source]);
+            neuron.preactivation = preactivation;
+            self.values[self.input_count + offset] = neuron.activation.activate(preactivation);
+        }
+        Ok(())
+    }
+
+    pub fn outputs(&self) -> Vec<ComplexFloat> {
+        self.outputs.iter().map(|&position| self.values[position]).collect()
+    }
+
+    // sum of |output - expected|^2 over the outputs
+    pub fn get_error(&self, expected_output_values: &[ComplexFloat]) -> Result<Float, String> {
+        if expected_output_values.len() != self.outputs.len() {
+            return Err(format!(
+                "Output sizes do not match. {} vs {}",
+                expected_output_values.len(),
+                self.outputs.len()
+            ));
+        }
+        Ok(self
+            .outputs()
+            .iter()
+            .zip(expected_output_values)
+            .map(|(out, expected)| (out - expected).norm_sqr())
+            .sum())
+    }
+
+    // one gradient descent step on the last propagated inputs, returns the error before the step
+    pub fn backpropagate(
+        &mut self,
+        expected_output_values: &[ComplexFloat],
+        learning_rate: Float,
+    ) -> Result<Float, String> {
+        let total_error = self.get_error(expected_output_values)?;
+        // d|y - t|^2 / dRe y + i * d|y - t|^2 / dIm y = 2 * (y - t)
+        let mut gradients = vec![Complex::new(0.0, 0.0); self.values.len()];
+        for (&position, expected) in self.outputs.iter().zip(expected_output_values) {
+            gradients[position] += (self.values[position] - expected) * 2.0;
+        }
+        for (offset, neuron) in self.neurons.iter_mut().enumerate().rev() {
+            let gradient = neuron
+                .activation
+                .backpropagate(neuron.preactivation, gradients[self.input_count + offset]);
+            for (source, weight) in neuron.synapses.iter_mut() {
+                gradients[*source] += gradient * weight.conj();
+                *weight -= gradient * self.values[*source].conj() * learning_rate;
+            }
+            neuron.bias -= gradient * learning_rate;
+        }
+        Ok(total_error)
+    }
+
+    fn to_config(&self) -> ComplexConfigJson {
+        let neurons = self
+            .neurons
+            .iter()
+            .enumerate()
+            .map(|(offset, neuron)| {
+                let defs = ComplexNeuronDefs {
+                    activation: neuron.activation.get_name().to_string(),
+                    bias: neuron.bias,
+                    synapses: neuron
+                        .synapses
+                        .iter()
+                        .map(|(source, weight)| (self.ids[*source].clone(), *weight))
+                        .collect(),
+                };
+                (self.ids[self.input_count + offset].clone(), defs)
+            })
+            .collect();
+        ComplexConfigJson {
+            inputs: self.ids[..self.input_count].to_vec(),
+            outputs: self
+                .outputs
+                .iter()
+                .map(|&position| self.ids[position].clone())
+                .collect(),
+            neurons,
+        }
+    }
+
+    pub fn print_as_json(&self) -> String {
+        serde_json::to_string_pretty(&self.to_config()).expect("Could not serialize the network")
+    }
+}
+
+impl FromStr for ComplexNetwork {
+    type Err = NeuralError;
+
+    fn from_str(json: &str) -> Result<Self, Self::Err> {
+        let cfg = serde_json::from_str(json).map_err(|e| NeuralError::ParseError(e.to_string()))?;
+        Self::from_config(cfg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"{"inputs": ["X", "Y"], "outputs": ["O"], "neurons": {
+        "H": {"activation": "CReLU", "bias": [0.1, -0.2], "synapses": {"X": [0.5, 0.3], "Y": [-0.4, 0.6]}},
+        "O": {"synapses": {"H": [0.7, -0.5], "X": [0.2, 0.1]}}
+    }}"#;
+
+    fn sample() -> ([ComplexFloat; 2], [ComplexFloat; 1]) {
+        (
+            [Complex::new(0.8, 0.3), Complex::new(-0.2, 0.9)],
+            [Complex::new(0.4, -0.6)],
+        )
+    }
+
+    fn error_with(change: impl Fn(&mut ComplexNetwork)) -> Float {
+        let (inputs, expected) = sample();
+        let mut nn: ComplexNetwork = CONFIG.parse().unwrap();
+        change(&mut nn);
+        nn.propagate(&inputs).unwrap();
+        nn.get_error(&expected).unwrap()
+    }
+
+    #[test]
+    fn crelu_clamps_each_part() {
+        let z = Complex::new(-1.5, 2.0);
+        assert_eq!(ComplexActivation::CReLU.activate(z), Complex::new(0.0, 2.0));
+        assert_eq!(
+            ComplexActivation::CReLU.backpropagate(z, Complex::new(3.0, 4.0)),
+            Complex::new(0.0, 4.0)
+        );
+        assert!(ComplexActivation::from_name("TanH").is_err());
+    }
+
+    #[test]
+    fn updates_follow_the_gradient_of_the_squared_magnitude() {
+        // the step of every weight is -learning_rate * (dL/dRe + i * dL/dIm), compared with
+        // central differences of the error
+        let (inputs, expected) = sample();
+        let learning_rate = 1e-3;
+        let mut nn: ComplexNetwork = CONFIG.parse().unwrap();
+        let before: Vec<Vec<ComplexFloat>> = nn
+            .neurons
+            .iter()
+            .map(|neuron| neuron.synapses.iter().map(|(_, weight)| *weight).collect())
+            .collect();
+        nn.propagate(&inputs).unwrap();
+        nn.backpropagate(&expected, learning_rate).unwrap();
+        // the differences lose precision in single precision, so the step and tolerance follow it
+        let h = Float::EPSILON.cbrt();
+        for (offset, weights) in before.iter().enumerate() {
+            for (position, weight) in weights.iter().enumerate() {
+                let slope = |delta: ComplexFloat| {
+                    let nudge = |sign: Float| {
+                        move |nn: &mut ComplexNetwork| nn.neurons[offset].synapses[position].1 += delta * sign
+                    };
+                    (error_with(nudge(1.0)) - error_with(nudge(-1.0))) / (2.0 * h)
+                };
+                let expected_step =
+                    -Complex::new(slope(Complex::new(h, 0.0)), slope(Complex::new(0.0, h))) * learning_rate;
+                let step = nn.neurons[offset].synapses[position].1 - weight;
+                assert!(
+                    (step - expected_step).norm() < 10.0 * h * learning_rate,
+                    "{} vs {}",
+                    step,
+                    expected_step
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn training_reduces_the_error_and_the_config_round_trips() {
+        let (inputs, expected) = sample();
+        let mut nn: ComplexNetwork = CONFIG.parse().unwrap();
+        nn.propagate(&inputs).unwrap();
+        let first = nn.backpropagate(&expected, 0.1).unwrap();
+        for _ in 0..50 {
+            nn.propagate(&inputs).unwrap();
+            nn.backpropagate(&expected, 0.1).unwrap();
+        }
+        nn.propagate(&inputs).unwrap();
+        assert!(nn.get_error(&expected).unwrap() < first * 1e-3);
+        let mut reloaded: ComplexNetwork = nn.print_as_json().parse().unwrap();
+        reloaded.propagate(&inputs).unwrap();
+        assert_eq!(reloaded.outputs(), nn.outputs());
+    }
+
+    #[test]
+    fn cycles_are_rejected() {
+        let config = r#"{"inputs": ["X"], "outputs": ["A"], "neurons": {
+            "A": {"synapses": {"X": [1.0, 0.0], "B": [1.0, 0.0]}},
+            "B": {"synapses": {"A": [1.0, 0.0]}}
+        }}"#;
+        assert!(config.parse::<ComplexNetwork>().is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use super::NeuralNetwork;
+use crate::float::Float;
+
+// Analytic and finite difference gradient of one bias or weight
+#[derive(Debug, Clone)]
+pub struct ParameterCheck {
+    pub neuron: String,
+    // source of the synapse, None for the bias
+    pub source: Option<String>,
+    pub analytic: Float,
+    pub numeric: Float,
+}
+
+impl ParameterCheck {
+    // absolute below 1, relative above, so tiny gradients don't blow the error up
+    pub fn error(&self) -> Float {
+        (self.analytic - self.numeric).abs() / self.analytic.abs().max(self.numeric.abs()).max(1.0)
+    }
+
+    pub fn name(&self) -> String {
+        match &self.source {
+            Some(source) => format!("synapse '{}' -> '{}'", source, self.neuron),
+            None => format!("bias of '{}'", self.neuron),
+        }
+    }
+}
+
+impl NeuralNetwork {
+    // Compares the gradients of compute_gradients with central differences of the loss for one
+    // sample of one value per input neuron. Only feed-forward networks keep no state that the extra
+    // propagations would change. The parameters are restored afterwards.
+    pub fn check_gradients(
+        &mut self,
+        input_values: &[Float],
+        expected_output_values: &[Float],
+        epsilon: Float,
+    ) -> Result<Vec<ParameterCheck>, String> {
+        if !self.is_feed_forward() {
+            return Err("Gradients can only be checked on feed-forward networks".to_string());
+        }
+        if input_values.len() != self.inputs.len() {
+            return Err(format!(
+                "Input sizes do not match. {} vs {}",
+                input_values.len(),
+                self.inputs.len()
+            ));
+        }
+        self.propagate_values(input_values);
+        let (_, gradients) = self.compute_gradients(expected_output_values)?;
+        // compute_gradients seeds the scaled derivative
+        let scale = self.loss_scale;
+
+        let mut checks = vec![];
+        for (id, bias_gradient, synapse_gradients) in gradients.iter() {
+            let rcneuron = self.neuron_map[id].clone();
+            let bias = rcneuron.borrow().get_bias();
+            let numeric = self.central_difference(input_values, expected_output_values, epsilon, |value| {
+                rcneuron.borrow_mut().set_bias(bias + value)
+            })?;
+            checks.push(ParameterCheck {
+                neuron: id.clone(),
+                source: None,
+                analytic: bias_gradient / scale,
+                numeric,
+            });
+            let residual_synapses = rcneuron.borrow().get_residual_synapses();
+            let weights = rcneuron.borrow().get_synapses_map();
+            for (source, gradient) in synapse_gradients.iter() {
+                // residual weights are fixed
+                if residual_synapses.contains(source) {
+                    continue;
+                }
+                let weight = weights[source];
+                let numeric = self.central_difference(input_values, expected_output_values, epsilon, |value| {
+                    let _ = rcneuron.borrow_mut().set_synapse_weight(source, weight + value);
+                })?;
+                checks.push(ParameterCheck {
+                    neuron: id.clone(),
+                    source: Some(source.clone()),
+                    analytic: gradient / scale,
+                    numeric,
+                });
+            }
+        }
+        checks.sort_by(|a, b| (&a.neuron, &a.source).cmp(&(&b.neuron, &b.source)));
+        Ok(checks)
+    }
+
+    // (loss(p + epsilon) - loss(p - epsilon)) / (2 * epsilon), shift(x) has to set p + x
+    fn central_difference<F: Fn(Float)>(
+        &mut self,
+        input_values: &[Float],
+        expected_output_values: &[Float],
+        epsilon: Float,
+        shift: F,
+    ) -> Result<Float, String> {
+        shift(epsilon);
+        self.propagate_values(input_values);
+        let plus = self.get_error(expected_output_values)?;
+        shift(-epsilon);
+        self.propagate_values(input_values);
+        let minus = self.get_error(expected_output_values)?;
+        shift(0.0);
+        Ok((plus - minus) / (2.0 * epsilon))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(json: &str) -> NeuralNetwork {
+        json.parse().expect("invalid test config")
+    }
+
+    #[test]
+    fn gradients_agree_with_central_differences() {
+        let mut nn = network(
+            r#"{"inputs": ["X", "Y"], "outputs": ["O", "P"], "neurons": {
+                "H": {"activation": "TanH", "synapses": {"X": 0.8, "Y": -0.4}, "bias": 0.1},
+                "G": {"activation": "SoftStep", "synapses": {"X": -0.6, "H": 0.9}, "bias": 0.2},
+                "O": {"synapses": {"H": 1.5, "G": -0.7}, "bias": 0.05},
+                "P": {"activation": "TanH", "synapses": {"G": 0.3, "Y": 1.1}}
+            }}"#,
+        );
+        let config = |nn: &NeuralNetwork| serde_json::to_value(nn.to_config()).unwrap();
+        let before = config(&nn);
+        let checks = nn.check_gradients(&[0.5, -0.3], &[0.3, -0.2], Float::EPSILON.cbrt()).unwrap();
+        // 4 biases and 8 weights
+        assert_eq!(checks.len(), 12);
+        for check in checks.iter() {
+            assert!(check.error() < 1e-3, "{}: {} vs {}", check.name(), check.analytic, check.numeric);
+        }
+        assert_eq!(config(&nn), before);
+    }
+
+    #[test]
+    fn a_kink_at_the_parameter_shows_up_as_a_mismatch() {
+        // the ReLU sits exactly on its kink, where the central difference averages both slopes
+        let mut nn = network(
+            r#"{"inputs": ["X"], "outputs": ["O"], "neurons": {
+                "H": {"activation": "ReLU", "synapses": {"X": 1.0}, "bias": -0.5},
+                "O": {"synapses": {"H": 1.0}}
+            }}"#,
+        );
+        let checks = nn.check_gradients(&[0.5], &[1.0], 1e-3).unwrap();
+        let worst = checks.iter().max_by(|a, b| a.error().total_cmp(&b.error())).unwrap();
+        assert_eq!(worst.neuron, "H");
+        assert!(worst.error() > 0.1);
+    }
+
+    #[test]
+    fn recurrent_networks_are_rejected() {
+        let mut nn = network(
+            r#"{"inputs": ["X"], "outputs": ["O"], "neurons": {
+                "O": {"synapses": {"X": 1.0, "O": 0.5}}
+            }}"#,
+        );
+        assert!(nn.check_gradients(&[1.0], &[0.0], 1e-3).is_err());
+    }
+}
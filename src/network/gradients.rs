@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::float::Float;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct NeuronGradients {
+    bias: Float,
+    // keyed by the id of the synapse's source
+    synapses: HashMap<String, Float>,
+}
+
+// Gradients of the loss with respect to every bias and weight, keyed by neuron ids so they can be
+// serialized, sent to another process and applied to a copy of the same network there.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Gradients {
+    neurons: HashMap<String, NeuronGradients>,
+}
+
+impl Gradients {
+    pub(super) fn insert(&mut self, id: String, bias: Float, synapses: HashMap<String, Float>) {
+        self.neurons.insert(id, NeuronGradients { bias, synapses });
+    }
+
+    pub(super) fn iter(&self) -> impl Iterator<Item = (&String, Float, &HashMap<String, Float>)> {
+        self.neurons.iter().map(|(id, gradients)| (id, gradients.bias, &gradients.synapses))
+    }
+
+    // sums the gradients of several samples or processes
+    pub fn accumulate(&mut self, other: &Gradients) {
+        for (id, gradients) in other.neurons.iter() {
+            let sum = self.neurons.entry(id.clone()).or_default();
+            sum.bias += gradients.bias;
+            for (source, gradient) in gradients.synapses.iter() {
+                *sum.synapses.entry(source.clone()).or_default() += gradient;
+            }
+        }
+    }
+
+    pub fn scale(&mut self, factor: Float) {
+        for gradients in self.neurons.values_mut() {
+            gradients.bias *= factor;
+            for gradient in gradients.synapses.values_mut() {
+                *gradient *= factor;
+            }
+        }
+    }
+}
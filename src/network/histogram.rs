@@ -0,0 +1,101 @@
+use std::io::{self, Write};
+
+use super::NeuralNetwork;
+use crate::float::Float;
+
+const BINS: usize = 10;
+// share of the activation's range at either end counted as saturated
+const SATURATION_MARGIN: Float = 0.05;
+// share of saturated activations above which a neuron is flagged
+const SATURATION_THRESHOLD: Float = 0.9;
+
+struct NeuronHistogram {
+    id: String,
+    activation: String,
+    range: Option<(Float, Float)>,
+    bins: [u64; BINS],
+    saturated: u64,
+    count: u64,
+    min: Float,
+    max: Float,
+    sum: Float,
+}
+
+impl NeuronHistogram {
+    fn record(&mut self, value: Float) {
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        if let Some((low, high)) = self.range {
+            let position = (value - low) / (high - low);
+            self.bins[((position * BINS as Float) as usize).min(BINS - 1)] += 1;
+            if position <= SATURATION_MARGIN || position >= 1.0 - SATURATION_MARGIN {
+                self.saturated += 1;
+            }
+        }
+    }
+}
+
+// Aggregates the activations of every non-input neuron over many propagations. Only activations
+// with a bounded range get a histogram, the others report their min, mean and max.
+pub struct ActivationHistograms {
+    neurons: Vec<NeuronHistogram>,
+}
+
+impl ActivationHistograms {
+    pub fn new(nn: &NeuralNetwork) -> Self {
+        let neurons = nn
+            .sorted_neurons
+            .iter()
+            .map(|neuron| neuron.borrow())
+            .filter(|neuron| !neuron.is_input())
+            .map(|neuron| NeuronHistogram {
+                id: neuron.get_id().to_string(),
+                activation: neuron.get_activation_name(),
+                range: neuron.get_saturation_range(),
+                bins: [0; BINS],
+                saturated: 0,
+                count: 0,
+                min: Float::INFINITY,
+                max: Float::NEG_INFINITY,
+                sum: 0.0,
+            })
+            .collect();
+        ActivationHistograms { neurons }
+    }
+
+    pub fn record(&mut self, nn: &NeuralNetwork) {
+        let neurons = nn.sorted_neurons.iter().map(|neuron| neuron.borrow()).filter(|neuron| !neuron.is_input());
+        for (histogram, neuron) in self.neurons.iter_mut().zip(neurons) {
+            histogram.record(neuron.get_activation_value());
+        }
+    }
+
+    pub fn write<W: Write + ?Sized>(&self, out: &mut W) -> io::Result<()> {
+        for histogram in self.neurons.iter() {
+            if histogram.count == 0 {
+                continue;
+            }
+            write!(
+                out,
+                "{} ({}): min {} mean {} max {}",
+                histogram.id,
+                histogram.activation,
+                histogram.min,
+                histogram.sum / histogram.count as Float,
+                histogram.max
+            )?;
+            if histogram.range.is_some() {
+                let bins: Vec<String> = histogram.bins.iter().map(|bin| bin.to_string()).collect();
+                let saturated = histogram.saturated as Float / histogram.count as Float;
+                write!(out, " [{}] saturated {:.1}%", bins.join(" "), saturated * 100.0)?;
+                if saturated >= SATURATION_THRESHOLD {
+                    write!(out, " LIKELY SATURATED")?;
+                }
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
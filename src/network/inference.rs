@@ -0,0 +1,122 @@
+use super::NeuralNetwork;
+use crate::float::Float;
+use crate::neurons::{ActivationFunction, Aggregation};
+
+#[derive(Debug, Clone)]
+struct InferenceNeuron {
+    activation: ActivationFunction,
+    aggregation: Aggregation,
+    bias: Float,
+    // (index of the source value, weight)
+    synapses: Vec<(usize, Float)>,
+    // index of the gating input
+    gate: Option<usize>,
+}
+
+// Immutable, index based copy of a network that can be shared between threads. Every inference
+// starts from a fresh state, so recurrent synapses see the same values as in the first propagation
+// of a freshly loaded network.
+#[derive(Debug, Clone)]
+pub struct InferenceModel {
+    // value index of every input neuron, in input order
+    inputs: Vec<usize>,
+    // every neuron in evaluation order, None for the inputs
+    neurons: Vec<Option<InferenceNeuron>>,
+    outputs: Vec<usize>,
+    // (index after which the group is normalized, value indices of its neurons)
+    softmax_groups: Vec<(usize, Vec<usize>)>,
+    // (mean, std) of every output
+    output_normalization: Option<Vec<(Float, Float)>>,
+    clamp_preactivation: Option<Float>,
+}
+
+impl InferenceModel {
+    pub(super) fn from_network(nn: &NeuralNetwork) -> Self {
+        let neurons = nn
+            .sorted_neurons
+            .iter()
+            .map(|neuron| {
+                let neuron = neuron.borrow();
+                (!neuron.is_input()).then(|| InferenceNeuron {
+                    activation: neuron.get_activation().clone(),
+                    aggregation: neuron.get_aggregation(),
+                    bias: neuron.get_bias(),
+                    synapses: neuron.get_indexed_synapses(),
+                    gate: neuron.get_gate_index(),
+                })
+            })
+            .collect();
+        InferenceModel {
+            inputs: nn.inputs.iter().map(|neuron| neuron.borrow().get_index()).collect(),
+            neurons,
+            outputs: nn.outputs.iter().map(|neuron| neuron.borrow().get_index()).collect(),
+            softmax_groups: nn
+                .softmax_groups
+                .iter()
+                .map(|group| {
+                    let indices = group.neurons.iter().map(|neuron| neuron.borrow().get_index()).collect();
+                    (group.last_index, indices)
+                })
+                .collect(),
+            output_normalization: nn.output_normalization.clone(),
+            clamp_preactivation: nn.clamp_preactivation,
+        }
+    }
+
+    pub fn get_input_count(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn get_output_count(&self) -> usize {
+        self.outputs.len()
+    }
+
+    // takes one value per input neuron, input indices and categorical inputs are not applied
+    pub fn infer(&self, input_values: &[Float]) -> Result<Vec<Float>, String> {
+        if input_values.len() != self.inputs.len() {
+            return Err(format!(
+                "Input sizes do not match. {} vs {}",
+                input_values.len(),
+                self.inputs.len()
+            ));
+        }
+        let mut values: Vec<Float> = vec![0.0; self.neurons.len()];
+        for (&index, &value) in self.inputs.iter().zip(input_values) {
+            values[index] = value;
+        }
+        for (index, neuron) in self.neurons.iter().enumerate() {
+            if let Some(neuron) = neuron {
+                values[index] = self.evaluate(neuron, &values);
+            }
+            for (_, indices) in self.softmax_groups.iter().filter(|(last_index, _)| *last_index == index) {
+                let max = indices.iter().map(|&i| values[i]).fold(Float::NEG_INFINITY, Float::max);
+                let sum: Float = indices.iter().map(|&i| (values[i] - max).exp()).sum();
+                for &i in indices {
+                    values[i] = (values[i] - max).exp() / sum;
+                }
+            }
+        }
+        let outputs = self.outputs.iter().map(|&index| values[index]);
+        Ok(match &self.output_normalization {
+            Some(normalization) => outputs.zip(normalization).map(|(value, (mean, std))| value * std + mean).collect(),
+            None => outputs.collect(),
+        })
+    }
+
+    fn evaluate(&self, neuron: &InferenceNeuron, values: &[Float]) -> Float {
+        let weighted_inputs = neuron.synapses.iter().map(|&(source, weight)| weight * values[source]);
+        let aggregated: Float = if neuron.activation.is_maxout() {
+            weighted_inputs.reduce(Float::max).unwrap_or(0.0)
+        } else if neuron.aggregation == Aggregation::Product {
+            weighted_inputs.product()
+        } else {
+            weighted_inputs.sum()
+        };
+        let gate = neuron.gate.map_or(1.0, |gate| values[gate]);
+        let preactivation = match self.clamp_preactivation {
+            Some(max) => (aggregated + neuron.bias).clamp(-max, max),
+            None => aggregated + neuron.bias,
+        };
+        neuron.activation.activation(preactivation) * gate
+    }
+}
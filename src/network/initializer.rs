@@ -0,0 +1,107 @@
+use crate::error::NeuralError;
+use crate::float::Float;
+use crate::neurons::ActivationFunction;
+use rand::distr::{Distribution, Uniform};
+use rand::Rng;
+use rand_distr::Normal;
+
+#[derive(Debug)]
+pub enum Initializer {
+    Uniform(Uniform<Float>),
+    Normal(Normal<Float>),
+}
+
+impl Initializer {
+    pub fn uniform(low: Float, high: Float) -> Result<Self, NeuralError> {
+        Uniform::new(low, high)
+            .map(Self::Uniform)
+            .map_err(|e| NeuralError::ParseError(format!("Invalid uniform range [{}, {}): {}", low, high, e)))
+    }
+
+    pub fn normal(mean: Float, std: Float) -> Result<Self, NeuralError> {
+        if std < 0.0 {
+            return Err(NeuralError::ParseError(format!("Standard deviation must not be negative, got {}", std)));
+        }
+        Normal::new(mean, std)
+            .map(Self::Normal)
+            .map_err(|e| NeuralError::ParseError(format!("Invalid normal distribution N({}, {}): {}", mean, std, e)))
+    }
+
+    // He for the ReLU family, Xavier for everything else
+    pub fn for_activation(activation: &ActivationFunction, fan_in: usize, fan_out: usize) -> Result<Self, NeuralError> {
+        match activation.get_name() {
+            "ReLU" | "LeakyReLU" | "ELU" | "GELU" => Self::normal(0.0, (2.0 / fan_in.max(1) as Float).sqrt()),
+            _ => {
+                let limit = (6.0 / (fan_in + fan_out).max(1) as Float).sqrt();
+                Self::uniform(-limit, limit)
+            }
+        }
+    }
+
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Float {
+        match self {
+            Self::Uniform(distribution) => distribution.sample(rng),
+            Self::Normal(distribution) => distribution.sample(rng),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::NeuralNetwork;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn uniform_weights_fall_within_the_range() {
+        let initializer = Initializer::uniform(-0.3, 0.7).unwrap();
+        let mut rng = StdRng::seed_from_u64(7);
+        let samples: Vec<Float> = (0..10000).map(|_| initializer.sample(&mut rng)).collect();
+        assert!(samples.iter().all(|&x| (-0.3..0.7).contains(&x)));
+        let mean = samples.iter().sum::<Float>() / samples.len() as Float;
+        assert!((mean - 0.2).abs() < 0.01, "mean {}", mean);
+    }
+
+    #[test]
+    fn normal_weights_have_the_requested_mean_and_std() {
+        let initializer = Initializer::normal(1.5, 0.2).unwrap();
+        let mut rng = StdRng::seed_from_u64(7);
+        let samples: Vec<Float> = (0..10000).map(|_| initializer.sample(&mut rng)).collect();
+        let mean = samples.iter().sum::<Float>() / samples.len() as Float;
+        let std = (samples.iter().map(|x| (x - mean).powi(2)).sum::<Float>() / samples.len() as Float).sqrt();
+        assert!((mean - 1.5).abs() < 0.01, "mean {}", mean);
+        assert!((std - 0.2).abs() < 0.01, "std {}", std);
+    }
+
+    #[test]
+    fn invalid_distributions_are_rejected() {
+        assert!(Initializer::uniform(1.0, 1.0).is_err());
+        assert!(Initializer::uniform(2.0, 1.0).is_err());
+        assert!(Initializer::normal(0.0, -1.0).is_err());
+    }
+
+    #[test]
+    fn only_missing_weights_are_initialized() {
+        let cfg = serde_json::from_str(
+            r#"{"inputs": ["X", "Y"], "outputs": ["O"], "neurons": {
+                "H": {"synapses": {"X": null, "Y": 5.0}},
+                "O": {"synapses": {"H": null, "X": null}}
+            }}"#,
+        )
+        .unwrap();
+        let initializer = Initializer::uniform(-0.3, 0.7).unwrap();
+        let mut rng = StdRng::seed_from_u64(7);
+        let nn = NeuralNetwork::initialize_config(cfg, Some(&initializer), true, &mut rng).unwrap();
+        for id in ["H", "O"] {
+            let neuron = nn.neuron_map[id].borrow();
+            assert!((-0.3..0.7).contains(&neuron.get_bias()));
+            for (source, weight) in neuron.get_synapses_map() {
+                match (id, source.as_str()) {
+                    ("H", "Y") => assert_eq!(weight, 5.0),
+                    _ => assert!((-0.3..0.7).contains(&weight), "{} -> {}: {}", source, id, weight),
+                }
+            }
+        }
+    }
+}
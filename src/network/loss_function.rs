@@ -1,14 +1,50 @@
-#[derive(Debug)]
+use crate::float::Float;
+
+// The error of a sample is the sum of the loss of every output and the derivative is that of
+// the loss as written, so the gradient always matches the reported error.
+#[derive(Debug, Default, Clone, Copy)]
 pub enum LossFunction {
+    // (x - y)^2, derivative 2 * (x - y)
+    #[default]
     LossSquared,
+    // (x - y)^2 / 2, derivative x - y
+    LossHalfSquared,
+    // |x - y|, derivative sign(x - y) and 0 at x == y
+    LossAbsolute,
+    // -(y * ln(x) + (1 - y) * ln(1 - x)), derivative (x - y) / (x * (1 - x)), for outputs and
+    // expected values in [0, 1]. x is clamped into [epsilon, 1 - epsilon] in both, so a confident
+    // but wrong output gives a large but finite loss and gradient.
+    LossCrossEntropy(Float),
 }
 
+pub const DEFAULT_CROSS_ENTROPY_EPSILON: Float = 1e-12;
+
 impl LossFunction {
     pub fn new() -> Self {
         LossFunction::LossSquared
     }
 
-    pub fn get_error(&self, out: &Vec<f64>, expected: &Vec<f64>) -> f64 {
+    // the names used by the config and by --loss, epsilon only matters for cross-entropy
+    pub fn from_name(name: &str, epsilon: Float) -> Option<Self> {
+        match name {
+            "squared" => Some(Self::LossSquared),
+            "half-squared" => Some(Self::LossHalfSquared),
+            "absolute" => Some(Self::LossAbsolute),
+            "cross-entropy" => Some(Self::LossCrossEntropy(epsilon)),
+            _ => None,
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::LossSquared => "squared",
+            Self::LossHalfSquared => "half-squared",
+            Self::LossAbsolute => "absolute",
+            Self::LossCrossEntropy(_) => "cross-entropy",
+        }
+    }
+
+    pub fn get_error(&self, out: &[Float], expected: &[Float]) -> Float {
         if out.len() != expected.len() {
             panic!(
                 "Sizes not matching when calculating error function. {} vs {}",
@@ -16,18 +52,71 @@ impl LossFunction {
                 expected.len()
             )
         }
+        out.iter()
+            .zip(expected.iter())
+            // NaN marks an unlabeled output
+            .filter(|(_, y)| !y.is_nan())
+            .map(|(x, y)| self.get_loss(*x, *y))
+            .sum()
+    }
+
+    pub fn get_loss(&self, out: Float, expected: Float) -> Float {
         match self {
-            LossFunction::LossSquared => out
-                .iter()
-                .zip(expected.iter())
-                .map(|(x, y)| (x - y).powi(2) )
-                .sum(),
+            Self::LossSquared => (out - expected).powi(2),
+            Self::LossHalfSquared => (out - expected).powi(2) / 2.0,
+            Self::LossAbsolute => (out - expected).abs(),
+            Self::LossCrossEntropy(epsilon) => {
+                let out = out.clamp(*epsilon, 1.0 - epsilon);
+                -(expected * out.ln() + (1.0 - expected) * (1.0 - out).ln())
+            }
         }
     }
 
-    pub fn get_derivative(&self, out: f64, expected: f64) -> f64 {
+    pub fn get_derivative(&self, out: Float, expected: Float) -> Float {
         match self {
             Self::LossSquared => (out - expected)*2.0,
+            Self::LossHalfSquared => out - expected,
+            Self::LossAbsolute if out == expected => 0.0,
+            Self::LossAbsolute => (out - expected).signum(),
+            Self::LossCrossEntropy(epsilon) => {
+                let out = out.clamp(*epsilon, 1.0 - epsilon);
+                (out - expected) / (out * (1.0 - out))
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derivatives_are_those_of_the_reported_loss() {
+        let h = Float::EPSILON.cbrt();
+        for name in ["squared", "half-squared", "absolute", "cross-entropy"] {
+            let loss = LossFunction::from_name(name, DEFAULT_CROSS_ENTROPY_EPSILON).unwrap();
+            for (out, expected) in [(0.3, 0.9), (0.8, 0.1), (0.55, 0.5)] {
+                let slope = (loss.get_loss(out + h, expected) - loss.get_loss(out - h, expected)) / (2.0 * h);
+                let derivative = loss.get_derivative(out, expected);
+                assert!((slope - derivative).abs() < 1e-3 * derivative.abs().max(1.0), "{}: {} vs {}", name, slope, derivative);
+            }
+        }
+        // squared has no 1/2 factor, so its derivative is twice the half-squared one
+        let (squared, half) = (LossFunction::LossSquared, LossFunction::LossHalfSquared);
+        assert_eq!(squared.get_loss(0.3, 0.9), 2.0 * half.get_loss(0.3, 0.9));
+        assert_eq!(squared.get_derivative(0.3, 0.9), 2.0 * (0.3 - 0.9));
+        assert_eq!(LossFunction::LossAbsolute.get_derivative(0.5, 0.5), 0.0);
+    }
+
+    #[test]
+    fn cross_entropy_of_a_confident_wrong_output_is_large_but_finite() {
+        let loss = LossFunction::from_name("cross-entropy", DEFAULT_CROSS_ENTROPY_EPSILON).unwrap();
+        let error = loss.get_loss(0.0, 1.0);
+        let derivative = loss.get_derivative(0.0, 1.0);
+        assert!(error.is_finite() && error > 20.0);
+        assert!(derivative.is_finite() && derivative < -1e6);
+        // a coarser epsilon clamps harder
+        let coarse = LossFunction::from_name("cross-entropy", 1e-3).unwrap();
+        assert!(coarse.get_derivative(0.0, 1.0) > derivative);
+    }
+}
@@ -0,0 +1,122 @@
+use flate2::Crc;
+
+use super::NeuralNetwork;
+use crate::error::NeuralError;
+use crate::float::Float;
+
+// An array of the archive, written as a .npy file
+enum Array {
+    Scalar(Float),
+    Floats(Vec<Float>),
+    Strings(Vec<String>),
+}
+
+impl Array {
+    fn to_npy(&self) -> Vec<u8> {
+        let (descr, shape, data) = match self {
+            Array::Scalar(value) => (float_descr(), "()".to_string(), value.to_le_bytes().to_vec()),
+            Array::Floats(values) => (
+                float_descr(),
+                format!("({},)", values.len()),
+                values.iter().flat_map(|value| value.to_le_bytes()).collect(),
+            ),
+            Array::Strings(values) => {
+                // fixed width UTF-32, shorter strings are padded with zeros
+                let width = values.iter().map(|value| value.chars().count()).max().unwrap_or(0).max(1);
+                let mut data = Vec::with_capacity(values.len() * width * 4);
+                for value in values {
+                    let chars: Vec<char> = value.chars().collect();
+                    for i in 0..width {
+                        data.extend((chars.get(i).copied().unwrap_or('\0') as u32).to_le_bytes());
+                    }
+                }
+                (format!("<U{}", width), format!("({},)", values.len()), data)
+            }
+        };
+        let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape);
+        // magic, version and header length take 10 bytes, the data starts 64 byte aligned
+        let padding = 63 - (10 + header.len()) % 64;
+        header.extend(std::iter::repeat_n(' ', padding));
+        header.push('\n');
+
+        let mut npy = b"\x93NUMPY\x01\x00".to_vec();
+        npy.extend((header.len() as u16).to_le_bytes());
+        npy.extend(header.as_bytes());
+        npy.extend(data);
+        npy
+    }
+}
+
+fn float_descr() -> String {
+    format!("<f{}", std::mem::size_of::<Float>())
+}
+
+// Every non-input neuron becomes three arrays, in neuron id order: '<id>.bias', '<id>.weights' and
+// '<id>.sources' holding the source of every weight, sorted by source id. np.load reads the archive.
+pub fn export(nn: &NeuralNetwork) -> Result<Vec<u8>, NeuralError> {
+    let mut neurons: Vec<_> = nn.sorted_neurons.iter().filter(|neuron| !neuron.borrow().is_input()).collect();
+    neurons.sort_by(|a, b| a.borrow().get_id().cmp(b.borrow().get_id()));
+
+    let mut arrays = vec![];
+    for neuron in neurons {
+        let neuron = neuron.borrow();
+        let mut synapses: Vec<(String, Float)> = neuron.get_synapses_map().into_iter().collect();
+        synapses.sort_by(|a, b| a.0.cmp(&b.0));
+        let (sources, weights) = synapses.into_iter().unzip();
+        arrays.push((format!("{}.bias", neuron.get_id()), Array::Scalar(neuron.get_bias())));
+        arrays.push((format!("{}.weights", neuron.get_id()), Array::Floats(weights)));
+        arrays.push((format!("{}.sources", neuron.get_id()), Array::Strings(sources)));
+    }
+    write_zip(arrays.into_iter().map(|(name, array)| (format!("{}.npy", name), array.to_npy())))
+}
+
+// A zip archive of uncompressed files, which is all an .npz is
+fn write_zip(files: impl Iterator<Item = (String, Vec<u8>)>) -> Result<Vec<u8>, NeuralError> {
+    let too_large = || NeuralError::NetworkError("The network is too large for an .npz archive".to_string());
+    let mut archive = vec![];
+    let mut directory = vec![];
+    let mut count: u16 = 0;
+    for (name, data) in files {
+        let mut crc = Crc::new();
+        crc.update(&data);
+        let offset = u32::try_from(archive.len()).map_err(|_| too_large())?;
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+        // version 2.0, no flags, stored, 1980-01-01 00:00
+        let mut fields = vec![];
+        fields.extend(20u16.to_le_bytes());
+        fields.extend(0u16.to_le_bytes());
+        fields.extend(0u16.to_le_bytes());
+        fields.extend(0u16.to_le_bytes());
+        fields.extend(0x21u16.to_le_bytes());
+        fields.extend(crc.sum().to_le_bytes());
+        fields.extend(size.to_le_bytes());
+        fields.extend(size.to_le_bytes());
+        fields.extend((name.len() as u16).to_le_bytes());
+        fields.extend(0u16.to_le_bytes());
+
+        archive.extend(0x04034b50u32.to_le_bytes());
+        archive.extend(&fields);
+        archive.extend(name.as_bytes());
+        archive.extend(data);
+
+        directory.extend(0x02014b50u32.to_le_bytes());
+        directory.extend(20u16.to_le_bytes());
+        directory.extend(&fields);
+        // comment length, disk, internal and external attributes
+        directory.extend([0u8; 10]);
+        directory.extend(offset.to_le_bytes());
+        directory.extend(name.as_bytes());
+        count = count.checked_add(1).ok_or_else(too_large)?;
+    }
+    let directory_offset = u32::try_from(archive.len()).map_err(|_| too_large())?;
+    let directory_size = directory.len() as u32;
+    archive.extend(directory);
+    archive.extend(0x06054b50u32.to_le_bytes());
+    archive.extend([0u8; 4]);
+    archive.extend(count.to_le_bytes());
+    archive.extend(count.to_le_bytes());
+    archive.extend(directory_size.to_le_bytes());
+    archive.extend(directory_offset.to_le_bytes());
+    archive.extend(0u16.to_le_bytes());
+    Ok(archive)
+}
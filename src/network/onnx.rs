@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+
+use prost::Message;
+
+use super::NeuralNetwork;
+use crate::error::NeuralError;
+use crate::float::Float;
+use crate::neurons::Aggregation;
+
+// the subset of onnx.proto needed to describe a feed-forward network
+#[derive(Clone, PartialEq, Message)]
+struct ModelProto {
+    #[prost(int64, tag = "1")]
+    ir_version: i64,
+    #[prost(string, tag = "2")]
+    producer_name: String,
+    #[prost(string, tag = "3")]
+    producer_version: String,
+    #[prost(message, optional, tag = "7")]
+    graph: Option<GraphProto>,
+    #[prost(message, repeated, tag = "8")]
+    opset_import: Vec<OperatorSetIdProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct OperatorSetIdProto {
+    #[prost(string, tag = "1")]
+    domain: String,
+    #[prost(int64, tag = "2")]
+    version: i64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct GraphProto {
+    #[prost(message, repeated, tag = "1")]
+    node: Vec<NodeProto>,
+    #[prost(string, tag = "2")]
+    name: String,
+    #[prost(message, repeated, tag = "5")]
+    initializer: Vec<TensorProto>,
+    #[prost(message, repeated, tag = "11")]
+    input: Vec<ValueInfoProto>,
+    #[prost(message, repeated, tag = "12")]
+    output: Vec<ValueInfoProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct NodeProto {
+    #[prost(string, repeated, tag = "1")]
+    input: Vec<String>,
+    #[prost(string, repeated, tag = "2")]
+    output: Vec<String>,
+    #[prost(string, tag = "3")]
+    name: String,
+    #[prost(string, tag = "4")]
+    op_type: String,
+    #[prost(message, repeated, tag = "5")]
+    attribute: Vec<AttributeProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct AttributeProto {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(int64, tag = "3")]
+    i: i64,
+    #[prost(int32, tag = "20")]
+    r#type: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TensorProto {
+    #[prost(int64, repeated, packed = "false", tag = "1")]
+    dims: Vec<i64>,
+    #[prost(int32, tag = "2")]
+    data_type: i32,
+    #[prost(float, repeated, tag = "4")]
+    float_data: Vec<f32>,
+    #[prost(int64, repeated, tag = "7")]
+    int64_data: Vec<i64>,
+    #[prost(string, tag = "8")]
+    name: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ValueInfoProto {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(message, optional, tag = "2")]
+    r#type: Option<TypeProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TypeProto {
+    #[prost(message, optional, tag = "1")]
+    tensor_type: Option<TensorTypeProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TensorTypeProto {
+    #[prost(int32, tag = "1")]
+    elem_type: i32,
+    #[prost(message, optional, tag = "2")]
+    shape: Option<TensorShapeProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TensorShapeProto {
+    #[prost(message, repeated, tag = "1")]
+    dim: Vec<Dimension>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Dimension {
+    #[prost(int64, tag = "1")]
+    dim_value: i64,
+    #[prost(string, tag = "2")]
+    dim_param: String,
+}
+
+// ids of the neurons sharing an activation, None being a plain Gemm
+type ActivationGroups = Vec<(Option<&'static str>, Vec<String>)>;
+
+const IR_VERSION: i64 = 8;
+const OPSET_VERSION: i64 = 13;
+const ATTRIBUTE_INT: i32 = 2;
+const TENSOR_FLOAT: i32 = 1;
+const TENSOR_INT64: i32 = 7;
+
+fn attribute(name: &str, value: i64) -> AttributeProto {
+    AttributeProto {
+        name: name.to_string(),
+        i: value,
+        r#type: ATTRIBUTE_INT,
+    }
+}
+
+fn node(op_type: &str, inputs: Vec<String>, output: &str, attributes: Vec<AttributeProto>) -> NodeProto {
+    NodeProto {
+        input: inputs,
+        output: vec![output.to_string()],
+        name: output.to_string(),
+        op_type: op_type.to_string(),
+        attribute: attributes,
+    }
+}
+
+fn batch_tensor(name: &str, size: usize) -> ValueInfoProto {
+    ValueInfoProto {
+        name: name.to_string(),
+        r#type: Some(TypeProto {
+            tensor_type: Some(TensorTypeProto {
+                elem_type: TENSOR_FLOAT,
+                shape: Some(TensorShapeProto {
+                    dim: vec![
+                        Dimension {
+                            dim_value: 0,
+                            dim_param: "batch".to_string(),
+                        },
+                        Dimension {
+                            dim_value: size as i64,
+                            dim_param: String::new(),
+                        },
+                    ],
+                }),
+            }),
+        }),
+    }
+}
+
+// ONNX tensors hold f32 whatever the precision of Float
+#[allow(clippy::unnecessary_cast)]
+fn to_f32(value: Float) -> f32 {
+    value as f32
+}
+
+fn activation_op(activation: &str) -> Result<Option<&'static str>, String> {
+    match activation {
+        "Linear" | "Identity" => Ok(None),
+        "ReLU" => Ok(Some("Relu")),
+        "SoftStep" => Ok(Some("Sigmoid")),
+        "TanH" => Ok(Some("Tanh")),
+        _ => Err(activation.to_string()),
+    }
+}
+
+// Every depth becomes one Gemm (plus activation) per activation used at that depth. Their results
+// are appended to a running "state" tensor holding the value of every neuron computed so far, so
+// synapses skipping depths are plain columns of the next weight matrix.
+pub fn export(nn: &NeuralNetwork) -> Result<Vec<u8>, NeuralError> {
+    if nn.clamp_preactivation.is_some() {
+        return Err(NeuralError::NetworkError(
+            "Can't export the pre-activation clamp to ONNX".to_string(),
+        ));
+    }
+    if nn.output_normalization.is_some() {
+        return Err(NeuralError::NetworkError(
+            "Can't export the output normalization to ONNX".to_string(),
+        ));
+    }
+    if let Some(neuron) = nn.sorted_neurons.iter().find(|neuron| neuron.borrow().get_aggregation() == Aggregation::Product) {
+        return Err(NeuralError::NetworkError(format!(
+            "Can't export the product aggregation of neuron '{}' to ONNX",
+            neuron.borrow().get_id()
+        )));
+    }
+    if let Some(group) = nn.softmax_groups.first() {
+        return Err(NeuralError::NetworkError(format!(
+            "Can't export the softmax group '{}' to ONNX",
+            group.name
+        )));
+    }
+    let depths: HashMap<String, u32> = nn
+        .sorted_neurons
+        .iter()
+        .map(|neuron| {
+            let neuron = neuron.borrow();
+            (neuron.get_id().to_string(), neuron.get_depth())
+        })
+        .collect();
+
+    let mut columns: HashMap<String, usize> = HashMap::new();
+    for (column, input) in nn.inputs.iter().enumerate() {
+        columns.insert(input.borrow().get_id().to_string(), column);
+    }
+    let mut state = "input".to_string();
+    let mut state_size = nn.inputs.len();
+
+    // neurons of each depth grouped by activation, sorted by id so the export is reproducible
+    let mut depth_groups: Vec<(u32, ActivationGroups)> = vec![];
+    for item in nn.sorted_neurons.iter() {
+        let neuron = item.borrow();
+        if neuron.is_input() {
+            continue;
+        }
+        let activation = neuron.get_activation_name();
+        let op = activation_op(&activation).map_err(|activation| {
+            NeuralError::NetworkError(format!(
+                "Can't export neuron '{}' with activation '{}' to ONNX, only Linear, Identity, ReLU, SoftStep and TanH are supported",
+                neuron.get_id(),
+                activation
+            ))
+        })?;
+        if let Some(gate) = neuron.get_gate_id() {
+            return Err(NeuralError::NetworkError(format!(
+                "Can't export neuron '{}' gated by '{}' to ONNX",
+                neuron.get_id(),
+                gate
+            )));
+        }
+        for lneuron_id in neuron.get_synapses_map().keys() {
+            if depths[lneuron_id] >= neuron.get_depth() {
+                return Err(NeuralError::NetworkError(format!(
+                    "Can't export the recurrent synapse from '{}' to '{}' to ONNX",
+                    lneuron_id,
+                    neuron.get_id()
+                )));
+            }
+        }
+        if depth_groups.last().is_none_or(|(depth, _)| *depth != neuron.get_depth()) {
+            depth_groups.push((neuron.get_depth(), vec![]));
+        }
+        let (_, groups) = depth_groups.last_mut().unwrap();
+        match groups.iter_mut().find(|(group_op, _)| *group_op == op) {
+            Some((_, ids)) => ids.push(neuron.get_id().to_string()),
+            None => groups.push((op, vec![neuron.get_id().to_string()])),
+        }
+    }
+
+    let mut nodes = vec![];
+    let mut initializers = vec![];
+    for (depth, mut groups) in depth_groups {
+        groups.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut parts = vec![state.clone()];
+        let mut new_columns = vec![];
+        for (group_index, (op, mut ids)) in groups.into_iter().enumerate() {
+            ids.sort();
+            let mut weights = vec![0.0f32; ids.len() * state_size];
+            let mut biases = Vec::with_capacity(ids.len());
+            for (row, id) in ids.iter().enumerate() {
+                let neuron = nn.neuron_map[id].borrow();
+                for (lneuron_id, weight) in neuron.get_synapses_map() {
+                    weights[row * state_size + columns[&lneuron_id]] += to_f32(weight);
+                }
+                biases.push(to_f32(neuron.get_bias()));
+            }
+            let prefix = format!("depth{}_{}", depth, group_index);
+            initializers.push(TensorProto {
+                dims: vec![ids.len() as i64, state_size as i64],
+                data_type: TENSOR_FLOAT,
+                float_data: weights,
+                int64_data: vec![],
+                name: format!("{}_weights", prefix),
+            });
+            initializers.push(TensorProto {
+                dims: vec![ids.len() as i64],
+                data_type: TENSOR_FLOAT,
+                float_data: biases,
+                int64_data: vec![],
+                name: format!("{}_biases", prefix),
+            });
+            let gemm = format!("{}_gemm", prefix);
+            nodes.push(node(
+                "Gemm",
+                vec![state.clone(), format!("{}_weights", prefix), format!("{}_biases", prefix)],
+                &gemm,
+                vec![attribute("transB", 1)],
+            ));
+            let part = match op {
+                Some(op) => {
+                    let activated = format!("{}_{}", prefix, op.to_lowercase());
+                    nodes.push(node(op, vec![gemm], &activated, vec![]));
+                    activated
+                }
+                None => gemm,
+            };
+            parts.push(part);
+            new_columns.extend(ids);
+        }
+        state = format!("depth{}_state", depth);
+        nodes.push(node("Concat", parts, &state, vec![attribute("axis", 1)]));
+        for id in new_columns {
+            columns.insert(id, state_size);
+            state_size += 1;
+        }
+    }
+
+    initializers.push(TensorProto {
+        dims: vec![nn.outputs.len() as i64],
+        data_type: TENSOR_INT64,
+        float_data: vec![],
+        int64_data: nn
+            .outputs
+            .iter()
+            .map(|output| columns[output.borrow().get_id()] as i64)
+            .collect(),
+        name: "output_columns".to_string(),
+    });
+    nodes.push(node(
+        "Gather",
+        vec![state, "output_columns".to_string()],
+        "output",
+        vec![attribute("axis", 1)],
+    ));
+
+    let model = ModelProto {
+        ir_version: IR_VERSION,
+        producer_name: "mmnn".to_string(),
+        producer_version: env!("CARGO_PKG_VERSION").to_string(),
+        graph: Some(GraphProto {
+            node: nodes,
+            name: "mmnn".to_string(),
+            initializer: initializers,
+            input: vec![batch_tensor("input", nn.inputs.len())],
+            output: vec![batch_tensor("output", nn.outputs.len())],
+        }),
+        opset_import: vec![OperatorSetIdProto {
+            domain: String::new(),
+            version: OPSET_VERSION,
+        }],
+    };
+    Ok(model.encode_to_vec())
+}
@@ -0,0 +1,73 @@
+use std::io::{self, Write};
+
+use super::NeuralNetwork;
+use crate::float::Float;
+
+struct OutputStatistic {
+    id: String,
+    count: u64,
+    mean: Float,
+    // sum of squared differences from the mean
+    m2: Float,
+    min: Float,
+    max: Float,
+}
+
+impl OutputStatistic {
+    fn record(&mut self, value: Float) {
+        // Welford's algorithm, stable without keeping the values around
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as Float;
+        self.m2 += delta * (value - self.mean);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+}
+
+// Running mean, standard deviation, min and max of every output over many propagations.
+pub struct OutputStatistics {
+    outputs: Vec<OutputStatistic>,
+}
+
+impl OutputStatistics {
+    pub fn new(nn: &NeuralNetwork) -> Self {
+        let outputs = nn
+            .outputs_named()
+            .into_iter()
+            .map(|(id, _)| OutputStatistic {
+                id,
+                count: 0,
+                mean: 0.0,
+                m2: 0.0,
+                min: Float::INFINITY,
+                max: Float::NEG_INFINITY,
+            })
+            .collect();
+        OutputStatistics { outputs }
+    }
+
+    pub fn record(&mut self, nn: &NeuralNetwork) {
+        for (statistic, value) in self.outputs.iter_mut().zip(nn.outputs()) {
+            statistic.record(value);
+        }
+    }
+
+    pub fn write<W: Write + ?Sized>(&self, out: &mut W) -> io::Result<()> {
+        for statistic in self.outputs.iter() {
+            if statistic.count == 0 {
+                continue;
+            }
+            writeln!(
+                out,
+                "{}: mean {} std {} min {} max {}",
+                statistic.id,
+                statistic.mean,
+                (statistic.m2 / statistic.count as Float).sqrt(),
+                statistic.min,
+                statistic.max
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,7 @@
 pub mod activation;
 pub mod neuron;
+pub mod optimizer;
 
 pub use activation::ActivationFunction;
-pub use neuron::{Neuron, NeuronType};
+pub use neuron::{Aggregation, Neuron, NeuronType};
+pub use optimizer::{Optimizer, RPropState};
@@ -1,8 +1,13 @@
-#[derive(Debug)]
+use crate::error::NeuralError;
+use crate::float::{consts, Float};
+
+#[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ActivationFunction {
     Identity,
     ArcTan,
-    Binary,
+    // fires when the input exceeds the threshold
+    Binary(Float),
     ISRU,
     LeakyReLU,
     Linear,
@@ -16,14 +21,32 @@ pub enum ActivationFunction {
     Swish,
     Sinusoid,
     ELiSH,
+    HardSigmoid,
+    HardSwish,
+    // takes the max of the weighted inputs instead of their sum
+    Maxout,
 }
 
 impl ActivationFunction {
     pub fn new(name: &str) -> ActivationFunction {
-        match name.to_lowercase().as_str() {
+        Self::with_params(name, &[]).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn with_params(name: &str, params: &[Float]) -> Result<ActivationFunction, NeuralError> {
+        let activation = match name.to_lowercase().as_str() {
+            "binary" => match params {
+                [] => return Ok(Self::Binary(0.0)),
+                [threshold] => return Ok(Self::Binary(*threshold)),
+                _ => {
+                    return Err(NeuralError::ParseError(format!(
+                        "Activation '{}' takes a single threshold parameter, got {}",
+                        name,
+                        params.len()
+                    )))
+                }
+            },
             "identity" => Self::Identity,
             "arctan" => Self::ArcTan,
-            "binary" => Self::Binary,
             "isru" => Self::ISRU,
             "leakyrelu" => Self::LeakyReLU,
             "linear" => Self::Linear,
@@ -38,19 +61,37 @@ impl ActivationFunction {
             "swish" => Self::Swish,
             "sinusoid" => Self::Sinusoid,
             "elish" => Self::ELiSH,
-            _ => panic!("Unknown activation function '{}'", name),
+            "hardsigmoid" => Self::HardSigmoid,
+            "hardswish" => Self::HardSwish,
+            "maxout" => Self::Maxout,
+            _ => return Err(NeuralError::ParseError(format!("Unknown activation function '{}'", name))),
+        };
+        if !params.is_empty() {
+            return Err(NeuralError::ParseError(format!(
+                "Activation '{}' takes no parameters, got {}",
+                name,
+                params.len()
+            )));
         }
+        Ok(activation)
     }
 
-    pub fn activation(&self, x: f64) -> f64 {
+    pub fn get_params(&self) -> Vec<Float> {
+        match self {
+            Self::Binary(threshold) => vec![*threshold],
+            _ => vec![],
+        }
+    }
+
+    pub fn activation(&self, x: Float) -> Float {
         match self {
             ActivationFunction::Identity => x,
             ActivationFunction::ArcTan => x.atan(),
-            ActivationFunction::Binary => {
-                if x > 0.0 {
-                    1_f64
+            ActivationFunction::Binary(threshold) => {
+                if x > *threshold {
+                    1.0
                 } else {
-                    0_f64
+                    0.0
                 }
             }
             ActivationFunction::ISRU => x / (1.0 + x.powi(2)).sqrt(),
@@ -66,49 +107,71 @@ impl ActivationFunction {
                 if x > 0.0 {
                     x
                 } else {
-                    0_f64
+                    0.0
                 }
             }
             ActivationFunction::ELU => {
                 if x >= 0.0 {
                     x
                 } else {
-                    0.1 * (std::f64::consts::E.powf(x) - 1.0)
+                    0.1 * (consts::E.powf(x) - 1.0)
                 }
             }
             ActivationFunction::GELU => {
                 0.5 * x
                     * (1.0
-                        + ((2.0 / std::f64::consts::PI).sqrt() * (x.powi(3) * 0.044715 + x)).tanh())
+                        + ((2.0 / consts::PI).sqrt() * (x.powi(3) * 0.044715 + x)).tanh())
             }
-            ActivationFunction::Gaussian => std::f64::consts::E.powf(-x.powi(2)),
+            ActivationFunction::Gaussian => consts::E.powf(-x.powi(2)),
             ActivationFunction::SoftSign => x / (1.0 + x.abs()),
-            ActivationFunction::SoftStep => 1_f64 / (1_f64 + std::f64::consts::E.powf(-x)),
+            ActivationFunction::SoftStep => 1.0 / (1.0 + consts::E.powf(-x)),
             ActivationFunction::TanH => {
-                let ex = std::f64::consts::E.powf(x);
-                let exc = std::f64::consts::E.powf(-x);
+                let ex = consts::E.powf(x);
+                let exc = consts::E.powf(-x);
                 (ex - exc) / (ex + exc)
             }
             ActivationFunction::Swish => {
-                let exc = std::f64::consts::E.powf(-x);
-                x * (1.0 - exc)
+                let exc = consts::E.powf(-x);
+                x / (1.0 + exc)
             }
             ActivationFunction::Sinusoid => x.sin(),
             ActivationFunction::ELiSH => {
                 if x >= 0.0 {
-                    (x) / (1.0 + std::f64::consts::E.powf(-x))
+                    (x) / (1.0 + consts::E.powf(-x))
                 } else {
-                    (std::f64::consts::E.powf(x) - 1.0) / (1.0 + std::f64::consts::E.powf(-x))
+                    (consts::E.powf(x) - 1.0) / (1.0 + consts::E.powf(-x))
                 }
             }
+            ActivationFunction::HardSigmoid => ((x + 3.0) / 6.0).clamp(0.0, 1.0),
+            ActivationFunction::HardSwish => x * ((x + 3.0) / 6.0).clamp(0.0, 1.0),
+            ActivationFunction::Maxout => x,
         }
     }
 
-    pub fn derivative(&self, x: f64) -> f64 {
+    // f(c * x) == c * f(x) for any c > 0
+    pub fn is_positive_homogeneous(&self) -> bool {
+        matches!(self, Self::Identity | Self::Linear | Self::ReLU | Self::LeakyReLU | Self::Maxout)
+    }
+
+    // the bounds a saturating activation approaches, where its derivative vanishes
+    pub fn saturation_range(&self) -> Option<(Float, Float)> {
+        match self {
+            Self::SoftStep | Self::HardSigmoid | Self::Gaussian => Some((0.0, 1.0)),
+            Self::TanH | Self::SoftSign | Self::ISRU => Some((-1.0, 1.0)),
+            Self::ArcTan => Some((-consts::FRAC_PI_2, consts::FRAC_PI_2)),
+            _ => None,
+        }
+    }
+
+    pub fn is_maxout(&self) -> bool {
+        matches!(self, Self::Maxout)
+    }
+
+    pub fn derivative(&self, x: Float) -> Float {
         match self {
             ActivationFunction::Identity => 1.0,
             ActivationFunction::ArcTan => 1.0 / (1.0 + x.powi(2)),
-            ActivationFunction::Binary => 0_f64,
+            ActivationFunction::Binary(_) => 0.0,
             ActivationFunction::ISRU => 1.0 / (1.0 + x.powi(2)).powf(1.5),
             ActivationFunction::LeakyReLU => {
                 if x >= 0.0 {
@@ -117,25 +180,25 @@ impl ActivationFunction {
                     0.01
                 }
             }
-            ActivationFunction::Linear => 1_f64,
+            ActivationFunction::Linear => 1.0,
             ActivationFunction::ReLU => {
                 if x > 0.0 {
-                    1_f64
+                    1.0
                 } else {
-                    0_f64
+                    0.0
                 }
             }
             ActivationFunction::ELU => {
                 if x >= 0.0 {
                     1.0
                 } else {
-                    0.1 * std::f64::consts::E.powf(x)
+                    0.1 * consts::E.powf(x)
                 }
             }
             ActivationFunction::GELU => {
                 let cdf = 0.5
-                    * ((2.0 / std::f64::consts::PI).sqrt() * (x + 0.044715 * x.powi(3))
-                        + x / (2.0_f64).sqrt())
+                    * ((2.0 / consts::PI).sqrt() * (x + 0.044715 * x.powi(3))
+                        + x / (2.0 as Float).sqrt())
                     .tanh()
                     + 0.5;
                 0.5 * (1.0 + cdf + x * (1.0 - cdf))
@@ -154,24 +217,41 @@ impl ActivationFunction {
             ActivationFunction::Sinusoid => x.cos(),
             ActivationFunction::ELiSH => {
                 if x >= 0.0 {
-                    (x * std::f64::consts::E.powf(x)
-                        + std::f64::consts::E.powf(2.0 * x)
-                        + std::f64::consts::E.powf(x))
-                        / (std::f64::consts::E.powf(2.0 * x) + 2.0 * std::f64::consts::E.powf(x) + 1.0)
+                    (x * consts::E.powf(x)
+                        + consts::E.powf(2.0 * x)
+                        + consts::E.powf(x))
+                        / (consts::E.powf(2.0 * x) + 2.0 * consts::E.powf(x) + 1.0)
                 } else {
-                    (2.0 * std::f64::consts::E.powf(2.0 * x) + std::f64::consts::E.powf(3.0 * x)
-                        - std::f64::consts::E.powf(x))
-                        / (std::f64::consts::E.powf(2.0 * x) + 2.0 * std::f64::consts::E.powf(x) + 1.0)
+                    (2.0 * consts::E.powf(2.0 * x) + consts::E.powf(3.0 * x)
+                        - consts::E.powf(x))
+                        / (consts::E.powf(2.0 * x) + 2.0 * consts::E.powf(x) + 1.0)
                 }
             }
+            ActivationFunction::HardSigmoid => {
+                if x > -3.0 && x < 3.0 {
+                    1.0 / 6.0
+                } else {
+                    0.0
+                }
+            }
+            ActivationFunction::HardSwish => {
+                if x <= -3.0 {
+                    0.0
+                } else if x >= 3.0 {
+                    1.0
+                } else {
+                    (2.0 * x + 3.0) / 6.0
+                }
+            }
+            ActivationFunction::Maxout => 1.0,
         }
     }
 
     pub fn get_name(&self) -> &'static str {
         match self {
             Self::Identity => "Identity",
-            Self::ArcTan => "ARCTAN",
-            Self::Binary => "Binary",
+            Self::ArcTan => "ArcTan",
+            Self::Binary(_) => "Binary",
             Self::ISRU => "ISRU",
             Self::LeakyReLU => "LeakyReLU",
             Self::Linear => "Linear",
@@ -185,6 +265,55 @@ impl ActivationFunction {
             Self::Swish => "Swish",
             Self::Sinusoid => "Sinusoid",
             Self::ELiSH => "ELiSH",
+            Self::HardSigmoid => "HardSigmoid",
+            Self::HardSwish => "HardSwish",
+            Self::Maxout => "Maxout",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(actual: Float, expected: Float, tolerance: Float) -> bool {
+        (actual - expected).abs() <= tolerance
+    }
+
+    #[test]
+    fn hard_sigmoid_approximates_the_sigmoid_up_to_its_clamps() {
+        let (hard, smooth) = (ActivationFunction::new("hardsigmoid"), ActivationFunction::new("sigmoid"));
+        assert_eq!(hard.activation(0.0), smooth.activation(0.0));
+        // the sigmoid is within 0.05 of its limits where the hard sigmoid reaches them
+        assert_eq!(hard.activation(-3.0), 0.0);
+        assert_eq!(hard.activation(3.0), 1.0);
+        assert!(close(smooth.activation(-3.0), 0.0, 0.05) && close(smooth.activation(3.0), 1.0, 0.05));
+        // and stays there beyond, with no slope left
+        for x in [-10.0, -3.5, 3.5, 10.0] {
+            assert!(close(hard.activation(x), smooth.activation(x), 0.05));
+            assert_eq!(hard.derivative(x), 0.0);
+        }
+        assert_eq!(hard.derivative(0.0), 1.0 / 6.0);
+    }
+
+    #[test]
+    fn hard_swish_approximates_swish_up_to_its_clamps() {
+        let (hard, smooth) = (ActivationFunction::new("hardswish"), ActivationFunction::new("swish"));
+        // swish itself is x * sigmoid(x), as its derivative assumes
+        let slope = (smooth.activation(1.001) - smooth.activation(0.999)) / 0.002;
+        assert!(close(slope, smooth.derivative(1.0), 1e-3));
+        assert_eq!(hard.activation(0.0), smooth.activation(0.0));
+        assert_eq!(hard.derivative(0.0), smooth.derivative(0.0));
+        // zero below -3 and the identity above 3, where swish is within 0.15 of both
+        assert_eq!(hard.activation(-3.0), 0.0);
+        assert_eq!(hard.activation(3.0), 3.0);
+        assert!(close(smooth.activation(-3.0), 0.0, 0.15) && close(smooth.activation(3.0), 3.0, 0.15));
+        assert_eq!(hard.derivative(-3.5), 0.0);
+        assert_eq!(hard.derivative(3.5), 1.0);
+        // continuous at the clamps
+        let step = 1e-3;
+        for x in [-3.0, 3.0] {
+            assert!(close(hard.activation(x - step), hard.activation(x + step), 3.0 * step));
         }
     }
 }
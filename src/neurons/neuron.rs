@@ -1,5 +1,9 @@
-use super::ActivationFunction;
+use super::optimizer::RPropState;
+use super::{ActivationFunction, Optimizer};
 use crate::error::NeuralError;
+use crate::float::Float;
+use rand::{Rng, RngExt};
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
@@ -8,31 +12,77 @@ use std::rc::Rc;
 pub enum NeuronType {
     Input,
     Normal,
+    // output computed from other neurons with fixed weights and no bias
+    Aggregate,
+}
+
+// How the weighted inputs of a neuron are combined into its pre-activation, before the bias is added
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregation {
+    #[default]
+    Sum,
+    // sigma-pi neuron, the product of every weight * input
+    Product,
+}
+
+impl Aggregation {
+    pub fn is_sum(&self) -> bool {
+        *self == Aggregation::Sum
+    }
 }
 
 #[derive(Debug)]
 pub struct Neuron {
     id: String,
     ntype: NeuronType,
-    synapses: Vec<(Rc<RefCell<Neuron>>, f64)>,
+    synapses: Vec<(Rc<RefCell<Neuron>>, Float)>,
     activation: ActivationFunction,
-    bias: f64,
+    aggregation: Aggregation,
+    bias: Float,
+    layer: Option<String>,
     depth: u32,
-    last_activation_value: f64,
-    backup_activation_value: f64,
+    // dense index into the network's error buffers
+    index: usize,
+    last_preactivation_value: Float,
+    last_activation_value: Float,
+    backup_activation_value: Float,
+    // synapse that won the last maxout aggregation
+    max_synapse: Option<usize>,
+    // product of every other synapse's weighted input in the last product aggregation, i.e. its
+    // derivative with respect to this synapse's weighted input
+    product_factors: Vec<Float>,
+    // indices of the identity shortcuts, their weight stays 1.0
+    residual_synapses: Vec<usize>,
+    // input neuron whose value multiplies the activation
+    gate: Option<Rc<RefCell<Neuron>>>,
+    // factor of every synapse's contribution in the current training step, empty keeps them all
+    dropconnect_mask: Vec<Float>,
+    // state of the bias followed by every synapse, created by the first RProp update
+    rprop: Vec<RPropState>,
 }
 
 impl Neuron {
-    pub fn new(id: &str, ntype: NeuronType, activation: ActivationFunction, bias: f64) -> Self {
+    pub fn new(id: &str, ntype: NeuronType, activation: ActivationFunction, bias: Float) -> Self {
         Neuron {
             id: id.to_owned(),
             ntype,
             synapses: vec![],
             activation,
+            aggregation: Aggregation::Sum,
             bias,
-            depth: std::u32::MAX,
+            layer: None,
+            depth: u32::MAX,
+            index: 0,
+            last_preactivation_value: 0.0,
             last_activation_value: 0.0,
             backup_activation_value: 0.0,
+            max_synapse: None,
+            product_factors: vec![],
+            residual_synapses: vec![],
+            gate: None,
+            dropconnect_mask: vec![],
+            rprop: vec![],
         }
     }
 
@@ -44,27 +94,106 @@ impl Neuron {
         self.depth
     }
 
+    pub fn set_depth(&mut self, depth: u32) {
+        self.depth = depth;
+    }
+
+    pub fn get_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn set_index(&mut self, index: usize) {
+        self.index = index;
+    }
+
     pub fn is_input(&self) -> bool {
         self.ntype == NeuronType::Input
     }
 
+    pub fn is_aggregate(&self) -> bool {
+        self.ntype == NeuronType::Aggregate
+    }
+
     pub fn get_activation_name(&self) -> String {
         self.activation.get_name().to_string()
     }
 
-    pub fn get_bias(&self) -> f64 {
+    pub fn get_activation(&self) -> &ActivationFunction {
+        &self.activation
+    }
+
+    pub fn set_activation(&mut self, activation: ActivationFunction) {
+        self.activation = activation;
+    }
+
+    pub fn get_activation_params(&self) -> Vec<Float> {
+        self.activation.get_params()
+    }
+
+    pub fn get_aggregation(&self) -> Aggregation {
+        self.aggregation
+    }
+
+    pub fn set_aggregation(&mut self, aggregation: Aggregation) {
+        self.aggregation = aggregation;
+    }
+
+    pub fn get_bias(&self) -> Float {
         self.bias
     }
 
-    pub fn get_activation_value(&self) -> f64 {
+    pub fn set_bias(&mut self, bias: Float) {
+        self.bias = bias;
+    }
+
+    pub fn get_saturation_range(&self) -> Option<(Float, Float)> {
+        self.activation.saturation_range()
+    }
+
+    pub fn is_positive_homogeneous(&self) -> bool {
+        self.activation.is_positive_homogeneous()
+    }
+
+    pub fn get_layer(&self) -> Option<&str> {
+        self.layer.as_deref()
+    }
+
+    pub fn set_layer(&mut self, layer: Option<String>) {
+        self.layer = layer;
+    }
+
+    pub fn get_activation_value(&self) -> Float {
         self.last_activation_value
     }
 
-    pub fn set_activation_value(&mut self, value: f64) {
+    pub fn set_activation_value(&mut self, value: Float) {
+        // input neurons have no activation so both values are the same
+        self.last_preactivation_value = value;
         self.last_activation_value = value;
     }
 
-    pub fn get_synapses_map(&self) -> HashMap<String, f64> {
+    // replaces the activation after a normalization over several neurons, e.g. a softmax
+    pub fn set_normalized_activation_value(&mut self, value: Float) {
+        self.last_activation_value = value;
+    }
+
+    pub fn get_preactivation_value(&self) -> Float {
+        self.last_preactivation_value
+    }
+
+    pub fn get_synapse_count(&self) -> usize {
+        self.synapses.len()
+    }
+
+    pub fn get_synapses_memory(&self) -> usize {
+        self.synapses.capacity() * std::mem::size_of::<(Rc<RefCell<Neuron>>, Float)>()
+    }
+
+    pub fn get_id_memory(&self) -> usize {
+        self.id.capacity()
+    }
+
+    pub fn get_synapses_map(&self) -> HashMap<String, Float> {
         let mut result = HashMap::with_capacity(self.synapses.len());
         for (lneuron, weight) in &self.synapses {
             let neuron_id = match lneuron.try_borrow() {
@@ -76,10 +205,85 @@ impl Neuron {
         result
     }
 
-    pub fn connect(&mut self, neuron: Rc<RefCell<Neuron>>, weight: f64) -> Result<(), NeuralError> {
+    // (index, weight) of every synapse, for representations without the Rc graph
+    pub fn get_indexed_synapses(&self) -> Vec<(usize, Float)> {
+        self.synapses
+            .iter()
+            .map(|(lneuron, weight)| match lneuron.try_borrow() {
+                Ok(lneuron) => (lneuron.index, *weight),
+                Err(_) => (self.index, *weight),
+            })
+            .collect()
+    }
+
+    // a self-loop can't be borrowed while this neuron is
+    pub fn has_recurrent_synapses(&self) -> bool {
+        self.synapses.iter().any(|(lneuron, _)| match lneuron.try_borrow() {
+            Ok(lneuron) => lneuron.depth >= self.depth,
+            Err(_) => true,
+        })
+    }
+
+    pub fn set_gate(&mut self, gate: Rc<RefCell<Neuron>>) {
+        self.gate = Some(gate);
+    }
+
+    pub fn get_gate_id(&self) -> Option<String> {
+        self.gate.as_ref().map(|gate| gate.borrow().get_id().to_string())
+    }
+
+    pub fn get_gate_index(&self) -> Option<usize> {
+        self.gate.as_ref().map(|gate| gate.borrow().index)
+    }
+
+    fn gate_value(&self) -> Float {
+        self.gate.as_ref().map_or(1.0, |gate| gate.borrow().last_activation_value)
+    }
+
+    pub fn get_residual_synapses(&self) -> Vec<String> {
+        self.residual_synapses
+            .iter()
+            .map(|&i| match self.synapses[i].0.try_borrow() {
+                Ok(neuron) => neuron.get_id().to_owned(),
+                Err(_) => self.get_id().to_owned(),
+            })
+            .collect()
+    }
+
+    // the weights in synapse order, for snapshots that don't need the ids
+    pub fn get_weights(&self) -> impl Iterator<Item = Float> + '_ {
+        self.synapses.iter().map(|(_, weight)| *weight)
+    }
+
+    pub fn set_weights(&mut self, weights: &[Float]) {
+        for ((_, weight), new_weight) in self.synapses.iter_mut().zip(weights) {
+            *weight = *new_weight;
+        }
+    }
+
+    pub fn set_synapse_weight(&mut self, neuron_id: &str, weight: Float) -> Result<(), NeuralError> {
+        let self_id = self.get_id().to_owned();
+        for (lneuron, synapse_weight) in self.synapses.iter_mut() {
+            let matches = match lneuron.try_borrow() {
+                Ok(neuron) => neuron.get_id() == neuron_id,
+                Err(_) => self_id == neuron_id,
+            };
+            if matches {
+                *synapse_weight = weight;
+                return Ok(());
+            }
+        }
+        Err(NeuralError::NeuronError(format!(
+            "Neuron '{}' has no synapse from '{}'",
+            self_id, neuron_id
+        )))
+    }
+
+    pub fn connect(&mut self, neuron: Rc<RefCell<Neuron>>, weight: Float) -> Result<(), NeuralError> {
         if self.is_input() {
+            // its value would be overwritten by the input line on every propagation
             return Err(NeuralError::NeuronError(format!(
-                "Cannot use input neuron '{}' as output to other neurons",
+                "Input neuron '{}' can't receive synapses, its value is set from the input",
                 self.get_id()
             )));
         }
@@ -87,8 +291,14 @@ impl Neuron {
         Ok(())
     }
 
+    pub fn connect_residual(&mut self, neuron: Rc<RefCell<Neuron>>) -> Result<(), NeuralError> {
+        self.connect(neuron, 1.0)?;
+        self.residual_synapses.push(self.synapses.len() - 1);
+        Ok(())
+    }
+
     pub fn calculate_depth(&mut self) -> Result<(), NeuralError> {
-        if self.depth != std::u32::MAX {
+        if self.depth != u32::MAX {
             return Ok(());
         }
         if self.synapses.is_empty() {
@@ -98,9 +308,9 @@ impl Neuron {
         let result = self
             .synapses
             .iter()
-            .map(|(lneuron, _)| match lneuron.try_borrow_mut() {
+            .filter_map(|(lneuron, _)| match lneuron.try_borrow_mut() {
                 Ok(mut neuron) => match neuron.depth {
-                    x if x != std::u32::MAX => Some(x + 1),
+                    x if x != u32::MAX => Some(x + 1),
                     _ => {
                         neuron.calculate_depth().ok()?;
                         Some(neuron.depth + 1)
@@ -108,7 +318,6 @@ impl Neuron {
                 },
                 Err(_) => None,
             })
-            .filter_map(|x| x)
             .max();
 
         match result {
@@ -123,62 +332,321 @@ impl Neuron {
         }
     }
 
-    pub fn propagate(&mut self) {
-        let sum_activations: f64 = self
+    // Drops every synapse but the residual ones with the given probability until the next call,
+    // scaling the kept ones by 1 / (1 - rate). A rate of 0 keeps them all.
+    pub fn sample_dropconnect<R: Rng + ?Sized>(&mut self, rate: Float, rng: &mut R) {
+        self.dropconnect_mask.clear();
+        if rate <= 0.0 {
+            return;
+        }
+        self.dropconnect_mask.resize(self.synapses.len(), 1.0);
+        // drawn in source id order, the synapse order depends on how the config was read
+        let ids = self.get_synapse_ids();
+        let mut order: Vec<usize> = (0..self.synapses.len()).collect();
+        order.sort_by(|&a, &b| ids[a].cmp(&ids[b]));
+        for index in order {
+            if !self.residual_synapses.contains(&index) {
+                self.dropconnect_mask[index] = match rng.random::<Float>() < rate {
+                    true => 0.0,
+                    false => 1.0 / (1.0 - rate),
+                };
+            }
+        }
+    }
+
+    pub fn clear_dropconnect(&mut self) {
+        self.dropconnect_mask.clear();
+    }
+
+    fn synapse_scale(&self, synapse_index: usize) -> Float {
+        self.dropconnect_mask.get(synapse_index).copied().unwrap_or(1.0)
+    }
+
+    // the pre-activation is bounded into [-max, max] when clamp is given, the derivative is then
+    // taken at the bounded value as well
+    pub fn propagate(&mut self, clamp: Option<Float>) {
+        let weighted_inputs = self
             .synapses
             .iter()
-            .map(|(lneuron, weight)| match lneuron.try_borrow_mut() {
-                Ok(neuron) => weight * neuron.last_activation_value,
-                Err(_) => weight * self.last_activation_value,
-            })
-            .sum();
+            .enumerate()
+            .map(|(i, (lneuron, weight))| match lneuron.try_borrow_mut() {
+                Ok(neuron) => weight * self.synapse_scale(i) * neuron.last_activation_value,
+                Err(_) => weight * self.synapse_scale(i) * self.last_activation_value,
+            });
+        let aggregated: Float = if self.activation.is_maxout() {
+            // ties go to the first synapse
+            let max = weighted_inputs.enumerate().fold(None, |max, (i, value)| match max {
+                Some((_, max_value)) if max_value >= value => max,
+                _ => Some((i, value)),
+            });
+            self.max_synapse = max.map(|(i, _)| i);
+            max.map_or(0.0, |(_, value)| value)
+        } else if self.aggregation == Aggregation::Product {
+            let terms: Vec<Float> = weighted_inputs.collect();
+            self.product_factors = products_of_others(&terms);
+            terms.iter().product()
+        } else {
+            weighted_inputs.sum()
+        };
         // used for recursive cases backpropagation
         self.backup_activation_value = self.last_activation_value;
-        self.last_activation_value = self.activation.activation(sum_activations + self.bias);
+        self.last_preactivation_value = match clamp {
+            Some(max) => (aggregated + self.bias).clamp(-max, max),
+            None => aggregated + self.bias,
+        };
+        self.last_activation_value = self.activation.activation(self.last_preactivation_value) * self.gate_value();
     }
 
-    pub fn backpropagate(&mut self, error_map: &mut HashMap<String, f64>, learning_rate: f64) {
-        let self_id = self.get_id().to_owned();
-        let accumulated_error = *error_map.entry(self_id.clone()).or_insert(0.0);
-        let error = accumulated_error * self.activation.derivative(self.last_activation_value);
-        let curr_depth = self.depth;
+    // derivative of the aggregate with respect to the weighted input of this synapse
+    fn aggregation_derivative(&self, synapse_index: usize) -> Float {
+        match self.aggregation {
+            Aggregation::Sum => 1.0,
+            Aggregation::Product => self.product_factors.get(synapse_index).copied().unwrap_or(1.0),
+        }
+    }
 
-        // Create a vector to store weight updates
-        let mut weight_updates = Vec::with_capacity(self.synapses.len());
+    fn routes_error(&self, synapse_index: usize) -> bool {
+        // a maxout neuron only passes the error to the synapse that won
+        !self.activation.is_maxout() || self.max_synapse == Some(synapse_index)
+    }
 
-        // First pass: Calculate all updates without modifying weights
+    pub fn backpropagate_error(&self, errors: &mut [Float]) {
+        // same error flow as backpropagate but without touching the weights
+        let accumulated_error = errors[self.index];
+        let error = accumulated_error * self.activation.derivative(self.last_preactivation_value) * self.gate_value();
+        if let Some(gate) = &self.gate {
+            let gate_index = gate.borrow().index;
+            errors[gate_index] += accumulated_error * self.activation.activation(self.last_preactivation_value);
+        }
         for (i, (rcneuron, weight)) in self.synapses.iter().enumerate() {
-            match rcneuron.try_borrow_mut() {
-                Ok(lneuron) => {
-                    let activation_value = if lneuron.depth <= curr_depth {
-                        lneuron.last_activation_value
-                    } else {
-                        lneuron.backup_activation_value
-                    };
-                    let neuron_id = lneuron.get_id().to_owned();
-                    let laccumulated = match error_map.get(&neuron_id) {
-                        Some(value) => value + accumulated_error * (*weight),
-                        None => accumulated_error * (*weight),
-                    };
-                    error_map.insert(neuron_id, laccumulated);
-                    weight_updates.push((i, accumulated_error * learning_rate * activation_value));
+            if !self.routes_error(i) {
+                continue;
+            }
+            // recurrent synapses are truncated, see backpropagate
+            if let Ok(lneuron) = rcneuron.try_borrow() {
+                if lneuron.depth < self.depth {
+                    errors[lneuron.index] += error * weight * self.synapse_scale(i) * self.aggregation_derivative(i);
                 }
-                Err(_) => {
-                    let laccumulated = match error_map.get(&self_id) {
-                        Some(value) => value + accumulated_error * (*weight),
-                        None => accumulated_error * (*weight),
-                    };
-                    error_map.insert(self_id.clone(), laccumulated);
-                    weight_updates.push((i, accumulated_error * learning_rate * self.backup_activation_value));
+            }
+        }
+    }
+
+    pub fn backpropagate(&mut self, errors: &mut [Float], learning_rate: Float, optimizer: Optimizer, freeze_bias: bool) {
+        if self.is_aggregate() {
+            // nothing to train, the error is only passed on
+            self.backpropagate_error(errors);
+            return;
+        }
+        let (bias_gradient, weight_gradients) = self.compute_gradients(errors);
+        self.apply_gradients(bias_gradient, &weight_gradients, learning_rate, optimizer, freeze_bias);
+    }
+
+    // Passes the error on like backpropagate and returns the gradient of the bias and of every
+    // synapse's weight in synapse order, without changing them.
+    pub fn compute_gradients(&self, errors: &mut [Float]) -> (Float, Vec<Float>) {
+        let accumulated_error = errors[self.index];
+        // the derivative is taken at the value the activation was applied to
+        // a closed gate zeroes the gradient
+        let error = accumulated_error * self.activation.derivative(self.last_preactivation_value) * self.gate_value();
+        let curr_depth = self.depth;
+
+        let mut weight_gradients = vec![0.0; self.synapses.len()];
+        for (i, (rcneuron, weight)) in self.synapses.iter().enumerate() {
+            if !self.routes_error(i) {
+                continue;
+            }
+            // a dropped synapse neither passes the error on nor gets a gradient
+            let error = error * self.synapse_scale(i) * self.aggregation_derivative(i);
+            // Neurons are processed deepest first and every synapse either comes from a shallower
+            // neuron or is recurrent, i.e. from an equally deep or deeper one. A shallower neuron
+            // receives its error before it is processed. A recurrent synapse carried the previous
+            // step's activation and its source has already been processed, so the error is not
+            // passed on through it (truncated backpropagation through time).
+            weight_gradients[i] = match rcneuron.try_borrow() {
+                Ok(lneuron) if lneuron.depth < curr_depth => {
+                    errors[lneuron.index] += error * (*weight);
+                    error * lneuron.last_activation_value
                 }
+                Ok(lneuron) => error * lneuron.backup_activation_value,
+                Err(_) => error * self.backup_activation_value,
+            };
+        }
+        // residual synapses only pass the error on
+        for &index in self.residual_synapses.iter() {
+            weight_gradients[index] = 0.0;
+        }
+        (error, weight_gradients)
+    }
+
+    pub fn apply_gradients(
+        &mut self,
+        bias_gradient: Float,
+        weight_gradients: &[Float],
+        learning_rate: Float,
+        optimizer: Optimizer,
+        freeze_bias: bool,
+    ) {
+        // a zero gradient leaves the bias as it is for every optimizer
+        let bias_gradient = if freeze_bias { 0.0 } else { bias_gradient };
+        if let Optimizer::RProp = optimizer {
+            self.apply_rprop(bias_gradient, weight_gradients, learning_rate);
+            return;
+        }
+        for (index, ((_, weight), gradient)) in self.synapses.iter_mut().zip(weight_gradients).enumerate() {
+            if !self.residual_synapses.contains(&index) {
+                *weight -= gradient * learning_rate;
             }
         }
+        self.bias -= bias_gradient * learning_rate;
+    }
+
+    // state of the bias followed by every synapse in synapse order, empty before the first RProp update
+    pub fn get_rprop_states(&self) -> &[RPropState] {
+        &self.rprop
+    }
+
+    pub fn set_rprop_states(&mut self, states: Vec<RPropState>) {
+        self.rprop = states;
+    }
 
-        // Second pass: Apply all weight updates
-        for (index, update) in weight_updates {
-            self.synapses[index].1 -= update;
+    fn apply_rprop(&mut self, bias_gradient: Float, weight_gradients: &[Float], initial_step: Float) {
+        // synapses connected after the first update start with the initial step too
+        self.rprop.resize(1 + self.synapses.len(), RPropState::new(initial_step));
+        self.bias += self.rprop[0].update(bias_gradient);
+        for (index, ((_, weight), gradient)) in self.synapses.iter_mut().zip(weight_gradients).enumerate() {
+            if !self.residual_synapses.contains(&index) {
+                *weight += self.rprop[1 + index].update(*gradient);
+            }
         }
+    }
+
+    // (source id, weight, source activation) of every synapse in synapse order, recurrent synapses
+    // with the value they carried in the last propagation
+    pub fn get_synapse_inputs(&self) -> Vec<(String, Float, Float)> {
+        self.synapses
+            .iter()
+            .map(|(lneuron, weight)| match lneuron.try_borrow() {
+                Ok(neuron) if neuron.depth < self.depth => (neuron.get_id().to_owned(), *weight, neuron.last_activation_value),
+                Ok(neuron) => (neuron.get_id().to_owned(), *weight, neuron.backup_activation_value),
+                Err(_) => (self.get_id().to_owned(), *weight, self.backup_activation_value),
+            })
+            .collect()
+    }
+
+    // source id of every synapse in synapse order, a self-loop has this neuron's id
+    pub fn get_synapse_ids(&self) -> Vec<String> {
+        self.synapses
+            .iter()
+            .map(|(lneuron, _)| match lneuron.try_borrow() {
+                Ok(neuron) => neuron.get_id().to_owned(),
+                Err(_) => self.get_id().to_owned(),
+            })
+            .collect()
+    }
+}
+
+// every product of all values but one without dividing, which would fail on zeros
+fn products_of_others(values: &[Float]) -> Vec<Float> {
+    let mut products = vec![1.0; values.len()];
+    let mut prefix = 1.0;
+    for (product, value) in products.iter_mut().zip(values) {
+        *product = prefix;
+        prefix *= value;
+    }
+    let mut suffix = 1.0;
+    for (product, value) in products.iter_mut().zip(values).rev() {
+        *product *= suffix;
+        suffix *= value;
+    }
+    products
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // input neurons take the first indices of the error buffer, the tested neuron the next one
+    fn inputs(values: &[Float]) -> Vec<Rc<RefCell<Neuron>>> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(index, &value)| {
+                let mut input = Neuron::new(&format!("I{}", index), NeuronType::Input, ActivationFunction::new("identity"), 0.0);
+                input.set_index(index);
+                input.set_depth(0);
+                input.set_activation_value(value);
+                Rc::new(RefCell::new(input))
+            })
+            .collect()
+    }
+
+    fn neuron(activation: &str, sources: &[Rc<RefCell<Neuron>>], weights: &[Float]) -> Neuron {
+        let mut neuron = Neuron::new("N", NeuronType::Normal, ActivationFunction::new(activation), 0.0);
+        neuron.set_index(sources.len());
+        neuron.set_depth(1);
+        for (source, &weight) in sources.iter().zip(weights) {
+            neuron.connect(source.clone(), weight).unwrap();
+        }
+        neuron
+    }
+
+    #[test]
+    fn maxout_routes_the_error_to_the_winning_synapse() {
+        let sources = inputs(&[1.0, 2.0, -1.0]);
+        let mut neuron = neuron("maxout", &sources, &[0.5, 0.4, -2.0]);
+        neuron.propagate(None);
+        // 0.5, 0.8 and 2.0, the third synapse wins
+        assert_eq!(neuron.get_activation_value(), 2.0);
+        let mut errors = vec![0.0, 0.0, 0.0, 1.5];
+        let (bias_gradient, weight_gradients) = neuron.compute_gradients(&mut errors);
+        assert_eq!(bias_gradient, 1.5);
+        // the error times the winning input and times its weight
+        assert_eq!(weight_gradients, vec![0.0, 0.0, -1.5]);
+        assert_eq!(&errors[..3], &[0.0, 0.0, -3.0]);
+    }
+
+    #[test]
+    fn dropconnect_at_rate_zero_keeps_every_synapse() {
+        let sources = inputs(&[1.0, 2.0, -1.0]);
+        let mut neuron = neuron("identity", &sources, &[0.5, 0.4, -2.0]);
+        let mut rng = StdRng::seed_from_u64(7);
+        neuron.sample_dropconnect(0.0, &mut rng);
+        neuron.propagate(None);
+        assert_eq!(neuron.get_activation_value(), 0.5 + 0.8 + 2.0);
+        let mut errors = vec![0.0, 0.0, 0.0, 1.0];
+        let (_, weight_gradients) = neuron.compute_gradients(&mut errors);
+        assert_eq!(weight_gradients, vec![1.0, 2.0, -1.0]);
+        assert_eq!(&errors[..3], &[0.5, 0.4, -2.0]);
+    }
+
+    #[test]
+    fn product_routes_the_error_through_the_other_terms() {
+        let sources = inputs(&[1.0, 3.0, 2.0]);
+        let mut neuron = neuron("identity", &sources, &[0.5, 2.0, -1.0]);
+        neuron.set_aggregation(Aggregation::Product);
+        neuron.propagate(None);
+        // terms 0.5, 6.0 and -2.0
+        assert_eq!(neuron.get_activation_value(), -6.0);
+        let mut errors = vec![0.0, 0.0, 0.0, 1.0];
+        let (_, weight_gradients) = neuron.compute_gradients(&mut errors);
+        // the product of the other terms, times the input for the weight and the weight for the source
+        assert_eq!(weight_gradients, vec![-12.0, -3.0, 6.0]);
+        assert_eq!(&errors[..3], &[-6.0, -2.0, -3.0]);
+    }
 
-        self.bias -= error * learning_rate;
+    #[test]
+    fn product_with_a_zero_input_only_passes_the_error_to_its_source() {
+        let sources = inputs(&[0.0, 3.0, 2.0]);
+        let mut neuron = neuron("identity", &sources, &[0.5, 2.0, -1.0]);
+        neuron.set_aggregation(Aggregation::Product);
+        neuron.propagate(None);
+        assert_eq!(neuron.get_activation_value(), 0.0);
+        let mut errors = vec![0.0, 0.0, 0.0, 1.0];
+        let (_, weight_gradients) = neuron.compute_gradients(&mut errors);
+        // every weight multiplies a zero, either its own input or another term
+        assert_eq!(weight_gradients, vec![0.0, 0.0, 0.0]);
+        assert_eq!(&errors[..3], &[0.5 * -12.0, 0.0, 0.0]);
     }
 }
@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+use crate::float::Float;
+
+const RPROP_INCREASE: Float = 1.2;
+const RPROP_DECREASE: Float = 0.5;
+const RPROP_MAX_STEP: Float = 50.0;
+const RPROP_MIN_STEP: Float = 1e-6;
+
+// How a neuron turns the gradients of its bias and weights into updates
+#[derive(Debug, Default, Clone, Copy)]
+pub enum Optimizer {
+    // steps against the gradient, scaled by the learning rate
+    #[default]
+    Sgd,
+    // only uses the sign of the gradient, every parameter adapts its own step which starts at the
+    // learning rate (RProp with weight backtracking)
+    RProp,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RPropState {
+    step: Float,
+    previous_gradient: Float,
+    previous_change: Float,
+}
+
+impl RPropState {
+    pub fn new(step: Float) -> Self {
+        RPropState {
+            step,
+            previous_gradient: 0.0,
+            previous_change: 0.0,
+        }
+    }
+
+    // returns the change of the parameter for this gradient
+    pub fn update(&mut self, gradient: Float) -> Float {
+        let product = gradient * self.previous_gradient;
+        if product < 0.0 {
+            // the last change jumped over a minimum, take it back and don't adapt the step on the
+            // next gradient
+            self.step = (self.step * RPROP_DECREASE).max(RPROP_MIN_STEP);
+            self.previous_gradient = 0.0;
+            return -std::mem::take(&mut self.previous_change);
+        }
+        if product > 0.0 {
+            self.step = (self.step * RPROP_INCREASE).min(RPROP_MAX_STEP);
+        }
+        // signum of 0.0 is 1.0
+        let change = if gradient == 0.0 { 0.0 } else { -gradient.signum() * self.step };
+        self.previous_gradient = gradient;
+        self.previous_change = change;
+        change
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Float, expected: Float) {
+        assert!((actual - expected).abs() < 1e-6, "{} vs {}", actual, expected);
+    }
+
+    #[test]
+    fn steps_grow_while_the_sign_holds_and_shrink_when_it_flips() {
+        let mut state = RPropState::new(0.1);
+        // no previous gradient, the step is kept
+        assert_close(state.update(2.0), -0.1);
+        assert_close(state.update(3.0), -0.12);
+        assert_close(state.update(0.5), -0.144);
+        // the sign flipped, the last change is taken back and the step halves
+        assert_close(state.update(-1.0), 0.144);
+        assert_close(state.step, 0.072);
+        // the step isn't adapted right after a flip
+        assert_close(state.update(-1.0), 0.072);
+        assert_close(state.update(-1.0), 0.0864);
+        assert_eq!(state.update(0.0), 0.0);
+    }
+
+    #[test]
+    fn steps_stay_within_their_bounds() {
+        // 40, 48 and then 57.6 clamped to 50
+        let mut state = RPropState::new(40.0);
+        for _ in 0..3 {
+            state.update(1.0);
+        }
+        assert_eq!(state.step, RPROP_MAX_STEP);
+        let mut state = RPropState::new(1.5e-6);
+        state.update(1.0);
+        state.update(-1.0);
+        assert_eq!(state.step, RPROP_MIN_STEP);
+    }
+
+    #[test]
+    fn minimizes_a_quadratic() {
+        // (w - 3)^2 from w = 0
+        let (mut w, mut state) = (0.0, RPropState::new(0.1));
+        for _ in 0..100 {
+            w += state.update(2.0 * (w - 3.0));
+        }
+        assert!((w - 3.0).abs() < 1e-3, "{}", w);
+    }
+}
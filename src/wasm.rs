@@ -0,0 +1,26 @@
+use wasm_bindgen::prelude::*;
+
+use crate::float::Float;
+use crate::network::NeuralNetwork;
+
+// A network for JavaScript, built from a JSON config since there is no filesystem to read it from
+#[wasm_bindgen]
+pub struct WasmNetwork {
+    nn: NeuralNetwork,
+}
+
+#[wasm_bindgen]
+impl WasmNetwork {
+    pub fn new_from_json(json: &str) -> Result<WasmNetwork, JsError> {
+        let nn = json.parse::<NeuralNetwork>().map_err(|e| JsError::new(&e.to_string()))?;
+        Ok(WasmNetwork { nn })
+    }
+
+    // takes a line's values like propagate does and returns the outputs
+    #[allow(clippy::unnecessary_cast)]
+    pub fn propagate(&mut self, inputs: Vec<f64>) -> Result<Vec<f64>, JsError> {
+        let inputs: Vec<Float> = inputs.into_iter().map(|value| value as Float).collect();
+        self.nn.propagate(&inputs).map_err(|e| JsError::new(&e))?;
+        Ok(self.nn.outputs().into_iter().map(|value| value as f64).collect())
+    }
+}